@@ -8,11 +8,24 @@
 ---| '"s16"'  # Signed short
 ---| '"u32"'  # Unsigned int
 ---| '"s32"'  # Signed int
----| '"s64"'  # Signed long. Unsigned is not supported.
+---| '"u64"'  # Unsigned long. Values from 2^63 wrap around to negative numbers, as in string.unpack()
+---| '"s64"'  # Signed long
 ---| '"f32"'  # Single precision float
 ---| '"f64"'  # Double precision float
+---| '"bool"'  # Byte that is true if it isn't zero
+---| '"fx16.16"'  # Signed fixed-point number with 16 integer and 16 fraction bits. Any split of 8, 16, 32 or 64 bits works
+---| '"u32le"'  # Add "le" to a type bigger than a byte to read it little-endian, e.g. "s16le", "f32le" or "fx16.16le"
+---| {bits:TypeSpecifier, shift:integer|nil, width:integer}  # Bit field of an unsigned integer type, shifted right by shift bits
+---| {cstring:integer}  # Text of at most this many bytes, ending at a zero byte
+---| {sjis:integer}  # Shift-JIS text of at most this many bytes, ending at a zero byte
+---| {utf16be:integer}  # UTF-16 text of at most this many bytes, ending at a zero code unit. Also {utf16le=n}
 ---| integer  # Number of bytes. Nonpositive numbers will return nil.
 
+---@alias FieldType
+---| TypeSpecifier
+---| string  # Name of a struct defined earlier with GameCube:DefineStruct()
+---| {array:FieldType, count:integer, stride:integer|nil}  # Stride defaults to the size of the element
+
 ---@alias AnyValue
 ---| boolean
 ---| number
@@ -33,6 +46,23 @@ function ScriptHost:CreateGameInterface() end
 ---@param interface GameInterface
 function ScriptHost:AddGameInterface(name, interface) end
 
+---@alias CommandErrorReason
+---| '"MissingArgument"'  # A required argument was not given
+---| '"BadValue"'  # An argument has an invalid value
+---| '"Unknown"'  # Any other error
+
+---Raise an error from a custom command handler that is sent back to the client as an ErrorReply.
+---This function does not return.
+---@param reason CommandErrorReason
+---@param argument string|nil  # Name of the offending argument
+---@param description string|nil
+function ScriptHost:CommandError(reason, argument, description) end
+
+---Read a file in the same directory as the script, or beneath it.
+---@param name string  # Path relative to the script's directory
+---@return string
+function ScriptHost:ReadFile(name) end
+
 
 ---@class GameCube
 GameCube = {}
@@ -40,6 +70,26 @@ GameCube = {}
 ---@type integer
 GameCube.GameIDAddress = nil
 
+---Address of the size of main memory in bytes, as a u32.
+---@type integer
+GameCube.MemorySizeAddress = nil
+
+---Address of the console type, as a u32.
+---@type integer
+GameCube.ConsoleTypeAddress = nil
+
+---Address of the lowest address of the heap the game allocates from, as a u32.
+---@type integer
+GameCube.ArenaLowAddress = nil
+
+---Address of the highest address of the heap the game allocates from, as a u32.
+---@type integer
+GameCube.ArenaHighAddress = nil
+
+---Get the parsed disc header of the running game. The header is read once for each connection to the console.
+---@return DiscInfo|nil
+function GameCube:GetDiscInfo() end
+
 ---Read a single value from an address in memory. Prefer to read multiple values at once with GameCube:ReadBatch() if
 ---you can, as each call of either method can be slow.
 ---@param address integer
@@ -53,15 +103,104 @@ function GameCube:ReadSingle(address, type, offset) end
 ---@return (integer|number|string|nil)[]
 function GameCube:Read(read_list) end
 
+---Define a struct to read with GameCube:ReadStruct(). Each script has its own struct names.
+---@param name string
+---@param fields [string, integer, FieldType][]  # Name, offset from the start of the struct, and type of each field
+---@param size integer|nil  # Distance between elements in arrays of the struct. Defaults to the end of the last field
+function GameCube:DefineStruct(name, fields, size) end
+
+---Read a struct defined with GameCube:DefineStruct() in a single batch.
+---@param address integer
+---@param name string
+---@param offset integer|nil  # If not nil, dereference the address and read the struct at this offset from it
+---@return table  # Fields keyed by name, with arrays as sequences. Fields that couldn't be read are nil
+function GameCube:ReadStruct(address, name, offset) end
+
+
+---@class DiscInfo
+---@field GameID string  # The full ID, e.g. "GM8E01"
+---@field GameCode string  # The console, game and region codes, e.g. "GM8E"
+---@field MakerCode string  # The publisher, e.g. "01" for Nintendo
+---@field RegionCode string  # e.g. "E"
+---@field Region string?  # e.g. "USA", or nil if the region code isn't known
+---@field DiscNumber integer  # 0 for the first disc
+---@field Revision integer
+---@field AudioStreaming boolean
+---@field StreamBufferSize integer
+DiscInfo = {}
+
 
 ---@class VariableStore
 VariableStore = {}
 
----Submit a variable to be sent to the tracker.
+---Submit a variable to be sent to the tracker. Names are paths separated by "/". Tables with string keys
+---are split into one variable per member beneath the name, replacing any variables already there.
 ---@param name string
 ---@param value AnyValue
 function VariableStore:WriteVariable(name, value) end
 
+---Remove a variable and any variables beneath it. The tracker is sent null for each removed variable,
+---and they are left out of later syncs. Only applies in GameInterface.GameWatcher.
+---@param name string
+function VariableStore:DeleteVariable(name) end
+
+---Remove every variable whose name starts with the given prefix, as with DeleteVariable().
+---@param prefix string
+function VariableStore:ClearPrefix(prefix) end
+
+---Start a transaction. Changes made until Commit() are only applied if it is called. Transactions can't be nested.
+function VariableStore:Begin() end
+
+---Apply the changes made since Begin().
+function VariableStore:Commit() end
+
+---Drop the changes made since Begin().
+function VariableStore:Abort() end
+
+---Drop every change made in this run of the game watcher, keeping the previous values.
+function VariableStore:MarkUnstable() end
+
+---Mark the game as loading. Changes are still applied, but variables with IgnoreNull keep their value.
+function VariableStore:MarkLoading() end
+
+
+---@class VariablePolicy
+---@field StableFor number?  # Seconds a value must stay the same before it is sent
+---@field IgnoreNull boolean?  # While the store is marked loading, keep the previous value instead of sending null or removing the variable
+---@field Monotonic boolean?  # Never send a number lower than the current value
+---@field MinInterval number?  # Minimum seconds between changes sent; the latest change is sent afterwards
+VariablePolicy = {}
+
+---@alias SchemaType
+---| "boolean"
+---| "number"
+---| "integer"
+---| "string"
+---| "array"
+
+---@alias SchemaStrictness
+---| "Warn"  # Report values that don't match, but write them anyway
+---| "Coerce"  # Convert values to match, and reject them if that isn't possible
+---| "Reject"  # Report values that don't match, and don't write them
+
+---@class VariableSchema
+---@field Type SchemaType?  # The type the value must have
+---@field Min number?  # The lowest number allowed
+---@field Max number?  # The highest number allowed
+---@field Values string[]?  # The strings allowed
+VariableSchema = {}
+
+---@class Normalization
+---@field LargeIntegers "String"|"Number"|nil  # How to send integers beyond 2^53, which trackers can't read exactly as numbers. Defaults to "String"
+---@field NonFinite "Null"|"Error"|nil  # Whether NaN and infinity are sent as null or are an error. Defaults to "Null"
+---@field FloatPrecision integer?  # Number of decimal places to round non-integer numbers to
+Normalization = {}
+
+---@class DerivedVariable
+---@field Deps string[]  # Names of the variables it is computed from, including variables beneath them
+---@field Func fun(values:table<string, AnyValue>):AnyValue  # Computes the value from the values of Deps, keyed by name
+DerivedVariable = {}
+
 
 ---@class GameInterface
 GameInterface = {}
@@ -75,17 +214,72 @@ GameInterface.Version = nil
 ---@type string[]?
 GameInterface.Features = nil
 
+---Names of the players' slots. Variables written beneath a slot's name are sent to clients for that slot,
+---without the slot's name, e.g. "P2/hearts" is sent as "hearts" for slot 1.
 ---@type string[]?
 GameInterface.Slots = nil
 
+---Values to keep across restarts of the bridge. If a snapshot file is in use, this table is saved with
+---it and restored when this interface connects to the same game again. Values must be representable in
+---JSON.
+---@type table
+GameInterface.PersistentData = {}
+
+---Game IDs from the disc header that this interface supports, e.g. "GM8E01". The header is checked
+---before VerifyFunc is called, so an interface that lists its game IDs doesn't need a VerifyFunc.
+---@type string[]?
+GameInterface.GameIDs = nil
+
+---Disc revisions this interface supports, checked along with GameIDs. Any revision is accepted if
+---this isn't set.
+---@type integer[]?
+GameInterface.Revisions = nil
+
 ---Called to determine if this interface can track the currently running game.
----Return true to accept, and false to reject.
----This method will be called repeatedly to ensure the correct game is still
----running.
+---Return true to accept, and false to reject. If GameIDs is set, this is only
+---called when connecting, once the disc header matches, and the header is
+---compared afterwards instead.
+---Otherwise this method will be called repeatedly to ensure the correct game is
+---still running.
 ---@type fun(self:GameInterface):boolean
 GameInterface.VerifyFunc = nil
 
+---When more than one interface accepts the running game, the one with the highest priority is
+---used, then the one registered first. Defaults to 0.
+---@type integer?
+GameInterface.Priority = nil
+
 ---Called to obtain the tracked variables from memory. Submit the variables read
 ---using the variable store's WriteVariable() method.
 ---@type fun(self:GameInterface, store:VariableStore)
 GameInterface.GameWatcher = nil
+
+---Policies for when changes to variables are sent to the tracker, keyed by variable name. A policy
+---also applies to variables beneath its name, and the longest matching name is used.
+---@type table<string, VariablePolicy>?
+GameInterface.VariablePolicies = nil
+
+---Schemas that values written to the store are checked against, keyed by variable name. A schema also
+---applies to variables beneath its name, and the longest matching name is used. Null is always allowed.
+---@type table<string, VariableSchema>?
+GameInterface.Schema = nil
+
+---What to do with values that don't match the schema. Defaults to "Warn".
+---@type SchemaStrictness?
+GameInterface.SchemaStrictness = nil
+
+---How numbers written to the store are turned into JSON for the tracker.
+---@type Normalization?
+GameInterface.Normalization = nil
+
+---Variables computed from other variables, keyed by variable name. All of them are computed when the interface
+---connects, and each one is computed again whenever one of its dependencies changes, including derived variables.
+---@type table<string, DerivedVariable>?
+GameInterface.Derived = nil
+
+---Handlers for custom commands sent by UAT clients, keyed by command name. A command is only accepted
+---if its name is also listed in Features. The handler receives the fields of the command object other
+---than "cmd". Variables written to the store are applied with the game watcher's on the next update,
+---and a non-nil return value is sent back in a Reply command.
+---@type table<string, fun(self:GameInterface, args:table, store:VariableStore):AnyValue>?
+GameInterface.Commands = nil
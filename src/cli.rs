@@ -0,0 +1,157 @@
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    lua::ScriptLimits,
+    uat::{UAT_PORT_BACKUP, UAT_PORT_MAIN},
+};
+
+pub const USAGE: &str = "\
+Usage: gamecube_uat_bridge [OPTIONS] <IP ADDRESS|dolphin> [SCRIPT PATHS...]
+
+Options:
+  --bind <ADDRESS>         Address to listen for UAT clients on [default: 127.0.0.1]
+  --port <PORT>            Port to listen on; repeat to give fallbacks [default: 65399, 44444]
+  --allow-origin <ORIGIN>  Allow browser clients from this origin; repeatable, or * for any
+  --interface <NAME>       Only use the game interface with this name, e.g. when several match
+  --trust <PATH>           Run scripts at this path with the full Lua standard library; repeatable
+  --script-time-limit <SECONDS>
+                           Time each call into a script can take [default: 2]
+  --script-memory-limit <BYTES>
+                           Memory each call into a script can allocate [default: 67108864]
+  --enable-read-memory     Accept ReadMemory commands from clients, for debugging scripts
+  --snapshot <FILE>        Save variables to this file, and restore them when restarted
+  --event-log <FILE>       Log variable changes and connection events to this file as JSON lines
+  --event-log-max-size <BYTES>
+                           Size at which to rotate the event log [default: 10485760]
+  --variable-map <FILE>    Rename, drop or transform variables sent to clients as given in this file";
+
+const DEFAULT_EVENT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_SCRIPT_TIME_LIMIT: Duration = Duration::from_secs(2);
+const DEFAULT_SCRIPT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Options given to the program on the command line.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub target: String,
+    pub search_paths: Vec<PathBuf>,
+    pub bind_address: IpAddr,
+    pub ports: Vec<u16>,
+    pub allowed_origins: Vec<String>,
+    pub interface: Option<String>,
+    pub trusted_scripts: Vec<PathBuf>,
+    pub script_limits: ScriptLimits,
+    pub enable_read_memory: bool,
+    pub snapshot: Option<PathBuf>,
+    pub event_log: Option<PathBuf>,
+    pub event_log_max_size: u64,
+    pub variable_map: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut target = None;
+        let mut search_paths = Vec::new();
+        let mut bind_address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut ports = Vec::new();
+        let mut allowed_origins = Vec::new();
+        let mut interface = None;
+        let mut trusted_scripts = Vec::new();
+        let mut script_limits = ScriptLimits {
+            time: DEFAULT_SCRIPT_TIME_LIMIT,
+            memory: DEFAULT_SCRIPT_MEMORY_LIMIT,
+        };
+        let mut enable_read_memory = false;
+        let mut snapshot = None;
+        let mut event_log = None;
+        let mut event_log_max_size = DEFAULT_EVENT_LOG_MAX_SIZE;
+        let mut variable_map = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("{name} requires a value"))
+            };
+            match arg.as_str() {
+                "--bind" => {
+                    let address = value("--bind")?;
+                    // Accept IPv6 addresses written in URL style, e.g. [::1]
+                    let address = address
+                        .strip_prefix('[')
+                        .and_then(|a| a.strip_suffix(']'))
+                        .unwrap_or(&address);
+                    bind_address = IpAddr::from_str(address)
+                        .map_err(|err| format!("invalid bind address {address}: {err}"))?;
+                }
+                "--port" => {
+                    let port = value("--port")?;
+                    ports.push(
+                        u16::from_str(&port)
+                            .map_err(|err| format!("invalid port {port}: {err}"))?,
+                    );
+                }
+                "--allow-origin" => allowed_origins.push(value("--allow-origin")?),
+                "--interface" => interface = Some(value("--interface")?),
+                "--trust" => trusted_scripts.push(PathBuf::from(value("--trust")?)),
+                "--script-time-limit" => {
+                    let seconds = value("--script-time-limit")?;
+                    script_limits.time = f64::from_str(&seconds)
+                        .map_err(|err| err.to_string())
+                        .and_then(|seconds| {
+                            Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())
+                        })
+                        .map_err(|err| format!("invalid time limit {seconds}: {err}"))?;
+                }
+                "--script-memory-limit" => {
+                    let size = value("--script-memory-limit")?;
+                    script_limits.memory = usize::from_str(&size)
+                        .map_err(|err| format!("invalid size {size}: {err}"))?;
+                }
+                "--enable-read-memory" => enable_read_memory = true,
+                "--snapshot" => snapshot = Some(PathBuf::from(value("--snapshot")?)),
+                "--event-log" => event_log = Some(PathBuf::from(value("--event-log")?)),
+                "--event-log-max-size" => {
+                    let size = value("--event-log-max-size")?;
+                    event_log_max_size = u64::from_str(&size)
+                        .map_err(|err| format!("invalid size {size}: {err}"))?;
+                }
+                "--variable-map" => variable_map = Some(PathBuf::from(value("--variable-map")?)),
+                "--help" | "-h" => Err(USAGE)?,
+                flag if flag.starts_with("--") => Err(format!("unknown option {flag}\n\n{USAGE}"))?,
+                _ => {
+                    if target.is_none() {
+                        target = Some(arg);
+                    } else {
+                        search_paths.push(PathBuf::from(arg));
+                    }
+                }
+            }
+        }
+
+        if ports.is_empty() {
+            ports.extend([UAT_PORT_MAIN, UAT_PORT_BACKUP]);
+        }
+
+        Ok(Self {
+            target: target.ok_or(format!("Need IP Address or to specify Dolphin\n\n{USAGE}"))?,
+            search_paths,
+            bind_address,
+            ports,
+            allowed_origins,
+            interface,
+            trusted_scripts,
+            script_limits,
+            enable_read_memory,
+            snapshot,
+            event_log,
+            event_log_max_size,
+            variable_map,
+        })
+    }
+}
@@ -90,7 +90,7 @@ impl OperationHeader {
     }
 
     fn get_bit(&self, bit: u8) -> bool {
-        return self.0 & bit != 0;
+        self.0 & bit != 0
     }
 
     pub fn has_read(&self) -> bool {
@@ -110,7 +110,7 @@ impl OperationHeader {
     }
 
     pub fn address_index(&self) -> u8 {
-        return self.0 & Self::ADDRESS_INDEX_MASK;
+        self.0 & Self::ADDRESS_INDEX_MASK
     }
 
     fn set_bit(&mut self, bit: u8, value: bool) {
@@ -147,7 +147,7 @@ impl OperationHeader {
 }
 
 fn write_to_socket(socket: &mut TcpStream, data: &[u8]) -> Result<Vec<u8>, io::Error> {
-    socket.write(data)?;
+    socket.write_all(data)?;
     let mut buffer = [0; 1024];
     let response = socket.read(&mut buffer)?;
     let result = Vec::from(&buffer[..response]);
@@ -225,8 +225,10 @@ impl GameCubeConnection for NintendontConnection {
                 match read {
                     Some(Read::Direct { address, size }) => {
                         result_info.push((address, size));
-                        cursor
-                            .write(&[OperationHeader::new(false, false, index).as_byte(), *size])?;
+                        cursor.write_all(&[
+                            OperationHeader::new(false, false, index).as_byte(),
+                            *size,
+                        ])?;
                     }
                     Some(Read::Indirect {
                         address,
@@ -234,9 +236,11 @@ impl GameCubeConnection for NintendontConnection {
                         size,
                     }) => {
                         result_info.push((address, size));
-                        cursor
-                            .write(&[OperationHeader::new(false, true, index).as_byte(), *size])?;
-                        cursor.write(&offset.to_be_bytes())?;
+                        cursor.write_all(&[
+                            OperationHeader::new(false, true, index).as_byte(),
+                            *size,
+                        ])?;
+                        cursor.write_all(&offset.to_be_bytes())?;
                     }
                     None => {}
                 }
@@ -267,19 +271,19 @@ impl GameCubeConnection for NintendontConnection {
 
                     assert!(data.len() <= self.connection_info.max_input_bytes as usize);
                     let mut result = write_to_socket(&mut self.socket.borrow_mut(), &data)?;
-                    if result.len() == 0 {
+                    if result.is_empty() {
                         return Err(io::Error::new(ErrorKind::InvalidData, "received no bytes"));
                     }
 
                     let mut data =
                         Cursor::new(result.split_off(((address_count - 1) / 8 + 1) as usize));
                     let success_bytes = result;
-                    for i in 0..result_info.len() {
+                    for (i, (_, size)) in result_info.iter().enumerate() {
                         let index = i / 8;
                         if success_bytes[index] & (1 << (i % 8)) == 0 {
                             results.push(None);
                         } else {
-                            let mut result = vec![0u8; *result_info[i].1 as usize];
+                            let mut result = vec![0u8; **size as usize];
                             data.read_exact(result.as_mut_slice())?;
                             results.push(Some(result));
                         }
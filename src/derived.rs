@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+
+use json::JsonValue;
+
+use crate::{
+    lua::{DerivedVariable, LuaInterface},
+    uat::variable::{is_in_subtree, VariableChange, VariableStore},
+};
+
+/// Order derived variables so that each comes after the derived variables it depends on, so they
+/// can all be computed in one pass. Variables in a cycle keep their relative order.
+pub fn sort_derived_variables(mut remaining: Vec<DerivedVariable>) -> Vec<DerivedVariable> {
+    let mut sorted = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let depends_on_remaining = |derived: &DerivedVariable| {
+            remaining.iter().any(|other| {
+                other.name != derived.name
+                    && derived
+                        .dependencies
+                        .iter()
+                        .any(|dependency| is_in_subtree(&other.name, dependency))
+            })
+        };
+        let next = remaining
+            .iter()
+            .position(|derived| !depends_on_remaining(derived))
+            .unwrap_or(0);
+        sorted.push(remaining.remove(next));
+    }
+    sorted
+}
+
+/// Compute the derived variables that depend on the changed variables, and write them to the
+/// store. Derived variables can depend on each other, so this repeats with the changes it makes,
+/// up to once per derived variable so that cycles can't go on forever. Returns the changes made.
+pub fn update_derived_variables(
+    lua_interface: &LuaInterface,
+    derived_variables: &[DerivedVariable],
+    store: &mut VariableStore,
+    changes: &[VariableChange],
+) -> Vec<VariableChange> {
+    let changed = changes.iter().map(|change| change.name.clone()).collect();
+    compute(lua_interface, derived_variables, store, Some(changed))
+}
+
+/// Compute every derived variable from the values in the store, such as after connecting or
+/// clearing the store, then the ones that depend on what changed. Returns the changes made.
+pub fn compute_all_derived_variables(
+    lua_interface: &LuaInterface,
+    derived_variables: &[DerivedVariable],
+    store: &mut VariableStore,
+) -> Vec<VariableChange> {
+    compute(lua_interface, derived_variables, store, None)
+}
+
+/// Compute the derived variables affected by `changed`, or all of them for None, repeating with
+/// the changes made.
+fn compute(
+    lua_interface: &LuaInterface,
+    derived_variables: &[DerivedVariable],
+    store: &mut VariableStore,
+    mut changed: Option<BTreeSet<String>>,
+) -> Vec<VariableChange> {
+    let mut derived_changes = Vec::new();
+    for _ in 0..derived_variables.len() {
+        if changed.as_ref().is_some_and(BTreeSet::is_empty) {
+            break;
+        }
+        let mut round_changes = Vec::new();
+        for derived in derived_variables {
+            let affected = changed.as_ref().is_none_or(|changed| {
+                derived
+                    .dependencies
+                    .iter()
+                    .any(|dependency| changed.iter().any(|name| is_in_subtree(name, dependency)))
+            });
+            if !affected {
+                continue;
+            }
+            let mut values = JsonValue::new_object();
+            for dependency in &derived.dependencies {
+                values[dependency.as_str()] = store.value(dependency).unwrap_or(JsonValue::Null);
+            }
+            match lua_interface.compute_derived(derived, &values) {
+                Ok(Some(value)) => {
+                    round_changes.extend(store.update_variable(&derived.name, value))
+                }
+                Ok(None) => {}
+                Err(err) => eprintln!("{}: {}", derived.name, err),
+            }
+        }
+        changed = Some(
+            round_changes
+                .iter()
+                .map(|change| change.name.clone())
+                .collect(),
+        );
+        derived_changes.extend(round_changes);
+    }
+    derived_changes
+}
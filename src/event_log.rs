@@ -0,0 +1,190 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use json::{object, JsonValue};
+
+use crate::uat::variable::VariableChange;
+
+/// Number of rotated logs to keep, named like `events.jsonl.1` with 1 being the newest.
+const ROTATED_LOG_COUNT: u32 = 5;
+
+/// Format a time as an RFC 3339 timestamp in UTC, with millisecond precision.
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Convert days since the epoch into a date in the proleptic Gregorian calendar. See
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// A log of events while tracking a game, written as one JSON object per line. The log is rotated
+/// once it grows past a maximum size.
+pub struct EventLog {
+    path: PathBuf,
+    max_size: u64,
+    file: File,
+    size: u64,
+    connected_at: Option<Instant>,
+    /// Whether the current game has re-verified since connecting
+    verified: bool,
+}
+
+impl EventLog {
+    pub fn open(path: impl AsRef<Path>, max_size: u64) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            file,
+            size,
+            connected_at: None,
+            verified: false,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..ROTATED_LOG_COUNT).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, event: &str, fields: JsonValue) {
+        let mut entry = object! {
+            event: event,
+            time: format_timestamp(SystemTime::now()),
+            since_connect: self.connected_at.map(|time| time.elapsed().as_secs_f64()),
+        };
+        for (key, value) in fields.entries() {
+            entry[key] = value.clone();
+        }
+        let mut line = entry.dump();
+        line.push('\n');
+
+        let result = (|| {
+            if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+                self.rotate()?;
+            }
+            self.file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+            Ok::<_, io::Error>(())
+        })();
+        if let Err(err) = result {
+            eprintln!("Couldn't write to event log: {}", err);
+        }
+    }
+
+    /// Log that an interface was found for the game, which starts the clock for `since_connect`.
+    pub fn connected(&mut self, interface: &str, game_id: Option<&str>) {
+        self.connected_at = Some(Instant::now());
+        self.verified = false;
+        self.write(
+            "connect",
+            object! {
+                interface: interface,
+                game_id: game_id,
+            },
+        );
+    }
+
+    /// Log that the current game re-verified. The game is re-verified every tick, so this is only
+    /// logged the first time after connecting.
+    pub fn verified(&mut self) {
+        if !self.verified {
+            self.verified = true;
+            self.write("verify", object! { success: true });
+        }
+    }
+
+    /// Log that the current game failed to re-verify.
+    pub fn verification_failed(&mut self, error: &str) {
+        self.write("verify", object! { success: false, error: error });
+    }
+
+    pub fn disconnected(&mut self, reason: &str) {
+        self.write("disconnect", object! { reason: reason });
+        self.connected_at = None;
+    }
+
+    pub fn variable_changed(&mut self, change: &VariableChange, slot: Option<i32>) {
+        self.write(
+            "change",
+            object! {
+                slot: slot,
+                name: change.name.as_str(),
+                old_value: change.old_value.clone(),
+                new_value: change.value(),
+                removed: change.new_value.is_none(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn timestamp(seconds: u64, millis: u64) -> String {
+        format_timestamp(UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn format_timestamp_as_rfc_3339() {
+        assert_eq!(timestamp(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(timestamp(951_782_400, 7), "2000-02-29T00:00:00.007Z");
+        assert_eq!(timestamp(1_709_251_199, 999), "2024-02-29T23:59:59.999Z");
+        assert_eq!(timestamp(4_102_444_800, 0), "2100-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn format_timestamp_clamps_times_before_the_epoch() {
+        let time = UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(format_timestamp(time), "1970-01-01T00:00:00.000Z");
+    }
+}
@@ -1,29 +1,146 @@
 use std::{
-    cell::RefCell, collections::HashMap, error::Error, fmt::Display, fs, io, mem, ops::Deref,
-    path::Path, rc::Rc,
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    fs, io, mem,
+    ops::{Deref, Range},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
 };
 
 use json::JsonValue;
-use mlua::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti, Lua, Table};
+use mlua::{
+    ChunkMode, FromLua, FromLuaMulti, Function, HookTriggers, IntoLua, IntoLuaMulti, Lua, Table,
+    VmState,
+};
 
 use crate::{
     connection::{GameCubeConnection, Read},
-    uat::command::InfoCommand,
+    normalization::{LargeIntegers, NonFinite, Normalization},
+    policy::VariablePolicy,
+    schema::{Schema, SchemaType, Strictness, VariableSchema},
+    uat::{
+        command::{
+            CustomCommand, ErrorReplyCommand, ErrorReplyReason, InfoCommand, ReadMemoryCommand,
+            ServerCommand,
+        },
+        variable::{is_writable, normalize_path},
+    },
 };
 
 const GCN_BASE_ADDRESS: u32 = 0x80000000;
+/// Size of the part of the disc header holding the game ID, disc number, revision and audio
+/// streaming settings.
+const DISC_HEADER_SIZE: u8 = 10;
+/// Globals the boot process leaves in low memory, given to scripts as GameCube.<Name>Address.
+const LOW_MEMORY_GLOBALS: &[(&str, u32)] = &[
+    ("MemorySizeAddress", GCN_BASE_ADDRESS + 0x28),
+    ("ConsoleTypeAddress", GCN_BASE_ADDRESS + 0x2C),
+    ("ArenaLowAddress", GCN_BASE_ADDRESS + 0x30),
+    ("ArenaHighAddress", GCN_BASE_ADDRESS + 0x34),
+];
+
+/// Global functions available to sandboxed scripts.
+const SANDBOX_GLOBALS: &[&str] = &[
+    "assert",
+    "error",
+    "getmetatable",
+    "ipairs",
+    "next",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawlen",
+    "rawset",
+    "select",
+    "setmetatable",
+    "tonumber",
+    "tostring",
+    "type",
+    "xpcall",
+    "_VERSION",
+];
+/// Standard libraries available to sandboxed scripts in full.
+const SANDBOX_LIBRARIES: &[&str] = &["coroutine", "math", "string", "table", "utf8"];
+/// Functions from the os library available to sandboxed scripts.
+const SANDBOX_OS_FUNCTIONS: &[&str] = &["clock", "date", "difftime", "time"];
+
+/// Number of values a struct can have, counting each element of its arrays.
+const MAX_STRUCT_VALUES: u64 = 65_536;
+
+/// Number of instructions a script runs between checks of its time limit.
+const LIMIT_CHECK_INTERVAL: u32 = 10_000;
+/// Wraps pcall, xpcall and coroutine.resume for sandboxed scripts, so that an error from going
+/// over the limits can't be caught and the script carry on. Takes the functions to wrap, a
+/// function telling whether an error is from the limits, and one raising it again.
+const PROTECTED_CALLS: &str = r#"
+local pcall, xpcall, resume, exceeded, raise = ...
+local function rethrow(ok, ...)
+    if not ok and exceeded((...)) then
+        raise()
+    end
+    return ok, ...
+end
+local function sandbox_pcall(f, ...)
+    return rethrow(pcall(f, ...))
+end
+local function sandbox_xpcall(f, handler, ...)
+    local limit_exceeded = false
+    -- Memory errors skip the handler
+    local function check(ok, ...)
+        if limit_exceeded or not ok and exceeded((...)) then
+            raise()
+        end
+        return ok, ...
+    end
+    return check(xpcall(f, function(err)
+        if exceeded(err) then
+            limit_exceeded = true
+            return err
+        end
+        return handler(err)
+    end, ...))
+end
+local function sandbox_resume(co, ...)
+    return rethrow(resume(co, ...))
+end
+return sandbox_pcall, sandbox_xpcall, sandbox_resume
+"#;
+/// coroutine.wrap built on a coroutine.create and coroutine.resume given to the chunk, since Lua
+/// doesn't give coroutines the hook of the thread that created them.
+const COROUTINE_WRAP: &str = r#"
+local create, resume = ...
+return function(f)
+    local co = create(f)
+    local function unwrap(ok, ...)
+        if not ok then
+            error((...), 0)
+        end
+        return ...
+    end
+    return function(...)
+        return unwrap(resume(co, ...))
+    end
+end
+"#;
+/// The error Lua raises when an allocation fails, such as when going over the memory limit.
+const OUT_OF_MEMORY_MESSAGE: &str = "not enough memory";
+
+/// Number of times in a row an interface can go over its limits before it's disabled.
+const MAX_LIMIT_FAILURES: u32 = 3;
 
 /// Coerce a value to true or false, following Lua semantics:
 /// null, nil, and false are false, and anything else is true.
 fn coerce_boolean(val: &mlua::Value) -> bool {
-    if val.is_null() {
-        false
-    } else if val.is_nil() {
+    if val.is_null() || val.is_nil() {
         false
-    } else if let Some(b) = val.as_boolean() {
-        b
     } else {
-        true
+        val.as_boolean().unwrap_or(true)
     }
 }
 
@@ -40,16 +157,40 @@ fn convert_lua_to_string(lua: &Lua, string: &mlua::Value) -> mlua::Result<String
         .to_owned())
 }
 
-/// Convert a Lua value into a JSON value
-fn convert_lua_to_json(lua: &Lua, value: &mlua::Value) -> mlua::Result<JsonValue> {
+/// Describe where the running script called into the bridge from, e.g. `scripts/prime.lua:42`.
+fn script_location(lua: &Lua) -> String {
+    lua.inspect_stack(1)
+        .map(|debug| {
+            let source = debug.source();
+            format!(
+                "{}:{}",
+                source.short_src.as_deref().unwrap_or("?"),
+                debug.curr_line()
+            )
+        })
+        .unwrap_or_else(|| "<unknown>".into())
+}
+
+/// Convert a Lua value into a JSON value, normalizing numbers that JSON readers can't handle
+fn convert_lua_to_json(
+    lua: &Lua,
+    value: &mlua::Value,
+    normalization: &Normalization,
+) -> mlua::Result<JsonValue> {
     if value.is_nil() {
         Ok(JsonValue::Null)
     } else if let Some(b) = value.as_boolean() {
         Ok(JsonValue::from(b))
     } else if let Some(i) = value.as_integer() {
-        Ok(JsonValue::from(i))
+        Ok(normalization.integer(i))
     } else if let Some(n) = value.as_number() {
-        Ok(JsonValue::from(n))
+        normalization
+            .float(n)
+            .ok_or_else(|| mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "JsonValue".into(),
+                message: Some(format!("{} can't be represented in JSON", n)),
+            })
     } else if let Some(s) = value.as_str() {
         Ok(JsonValue::from(s.deref()))
     } else if let Some(table) = value.as_table() {
@@ -73,7 +214,7 @@ fn convert_lua_to_json(lua: &Lua, value: &mlua::Value) -> mlua::Result<JsonValue
                         start,
                         |prev, next| if next == prev + 1 { Some(next) } else { None },
                     )
-                    .map(|end| start..end)
+                    .map(|end| start..=end)
             } else {
                 None
             }
@@ -85,7 +226,7 @@ fn convert_lua_to_json(lua: &Lua, value: &mlua::Value) -> mlua::Result<JsonValue
                 keys.map(|i| {
                     table
                         .get(i)
-                        .and_then(|v: mlua::Value| convert_lua_to_json(lua, &v))
+                        .and_then(|v: mlua::Value| convert_lua_to_json(lua, &v, normalization))
                 })
                 .collect::<mlua::Result<Vec<JsonValue>>>()?,
             ))
@@ -97,7 +238,7 @@ fn convert_lua_to_json(lua: &Lua, value: &mlua::Value) -> mlua::Result<JsonValue
                         result.and_then(|(k, v): (mlua::Value, mlua::Value)| {
                             Ok((
                                 convert_lua_to_string(lua, &k)?,
-                                convert_lua_to_json(lua, &v)?,
+                                convert_lua_to_json(lua, &v, normalization)?,
                             ))
                         })
                     })
@@ -113,6 +254,35 @@ fn convert_lua_to_json(lua: &Lua, value: &mlua::Value) -> mlua::Result<JsonValue
     }
 }
 
+/// Convert a JSON value into a Lua value
+fn convert_json_to_lua(lua: &Lua, value: &JsonValue) -> mlua::Result<mlua::Value> {
+    match value {
+        JsonValue::Null => Ok(mlua::Value::Nil),
+        JsonValue::Boolean(b) => b.into_lua(lua),
+        JsonValue::Number(n) => match i64::try_from(*n) {
+            Ok(i) => i.into_lua(lua),
+            Err(_) => f64::from(*n).into_lua(lua),
+        },
+        JsonValue::Short(_) | JsonValue::String(_) => value.as_str().into_lua(lua),
+        JsonValue::Array(array) => lua
+            .create_sequence_from(
+                array
+                    .iter()
+                    .map(|v| convert_json_to_lua(lua, v))
+                    .collect::<mlua::Result<Vec<_>>>()?,
+            )?
+            .into_lua(lua),
+        JsonValue::Object(object) => lua
+            .create_table_from(
+                object
+                    .iter()
+                    .map(|(k, v)| Ok((k, convert_json_to_lua(lua, v)?)))
+                    .collect::<mlua::Result<Vec<_>>>()?,
+            )?
+            .into_lua(lua),
+    }
+}
+
 macro_rules! bytes_to_lua {
     ($type_name:ty, $bytes:ident, $lua:ident) => {{
         assert_eq!($bytes.len(), mem::size_of::<$type_name>());
@@ -123,12 +293,19 @@ macro_rules! bytes_to_lua {
     }};
 }
 
+/// Read bytes as a big-endian unsigned integer.
+fn bytes_to_unsigned(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
 fn convert_bytes(
     lua: &Lua,
     bytes: Option<Vec<u8>>,
     ty: &TypeSpecifier,
 ) -> mlua::Result<mlua::Value> {
-    let bytes = match bytes {
+    let mut bytes = match bytes {
         Some(bytes) => bytes,
         None => return Ok(mlua::Value::Nil),
     };
@@ -140,8 +317,34 @@ fn convert_bytes(
         TypeSpecifier::U32 => bytes_to_lua!(u32, bytes, lua),
         TypeSpecifier::S32 => bytes_to_lua!(i32, bytes, lua),
         TypeSpecifier::F32 => bytes_to_lua!(f32, bytes, lua),
-        TypeSpecifier::S64 => bytes_to_lua!(i64, bytes, lua),
+        // Lua integers are signed, so values from 2^63 wrap around like they do in string.unpack()
+        TypeSpecifier::U64 | TypeSpecifier::S64 => bytes_to_lua!(i64, bytes, lua),
         TypeSpecifier::F64 => bytes_to_lua!(f64, bytes, lua),
+        TypeSpecifier::Bool => bytes.iter().any(|byte| *byte != 0).into_lua(lua),
+        TypeSpecifier::Fixed { fraction_bits, .. } => {
+            // Sign extend the value to 64 bits
+            let unused_bits = 64 - 8 * bytes.len() as u32;
+            let value = ((bytes_to_unsigned(&bytes) << unused_bits) as i64) >> unused_bits;
+            (value as f64 / 2f64.powi(*fraction_bits as i32)).into_lua(lua)
+        }
+        TypeSpecifier::Bits { base, shift, width } => {
+            if matches!(**base, TypeSpecifier::LittleEndian(_)) {
+                bytes.reverse();
+            }
+            let value = bytes_to_unsigned(&bytes) >> shift;
+            let value = match width {
+                64 => value,
+                width => value & ((1 << width) - 1),
+            };
+            (value as i64).into_lua(lua)
+        }
+        TypeSpecifier::LittleEndian(ty) => {
+            bytes.reverse();
+            convert_bytes(lua, Some(bytes), ty)
+        }
+        TypeSpecifier::String { encoding, .. } => lua
+            .create_string(encoding.decode(&bytes).as_bytes())
+            .map(mlua::Value::String),
         TypeSpecifier::Bytes(size) => {
             assert_eq!(bytes.len(), *size as usize);
             mlua::String::wrap(bytes).into_lua(lua)
@@ -149,6 +352,47 @@ fn convert_bytes(
     }
 }
 
+/// How text is stored in memory.
+#[derive(Debug, Clone, Copy)]
+enum StringEncoding {
+    /// UTF-8, which includes ASCII
+    Utf8,
+    ShiftJis,
+    Utf16Be,
+    Utf16Le,
+}
+
+impl StringEncoding {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cstring" => Some(Self::Utf8),
+            "sjis" => Some(Self::ShiftJis),
+            "utf16be" => Some(Self::Utf16Be),
+            "utf16le" => Some(Self::Utf16Le),
+            _ => None,
+        }
+    }
+
+    /// Decode text up to its terminator, which is a zero byte, or a zero code unit for UTF-16.
+    /// Invalid sequences are replaced with U+FFFD.
+    fn decode(self, bytes: &[u8]) -> String {
+        let (encoding, unit_size) = match self {
+            Self::Utf8 => (encoding_rs::UTF_8, 1),
+            Self::ShiftJis => (encoding_rs::SHIFT_JIS, 1),
+            Self::Utf16Be => (encoding_rs::UTF_16BE, 2),
+            Self::Utf16Le => (encoding_rs::UTF_16LE, 2),
+        };
+        let end = bytes
+            .chunks(unit_size)
+            .position(|unit| unit.iter().all(|byte| *byte == 0))
+            .map_or(bytes.len(), |units| units * unit_size);
+        encoding
+            .decode_without_bom_handling(&bytes[..end])
+            .0
+            .into_owned()
+    }
+}
+
 #[derive(Debug, Clone)]
 enum TypeSpecifier {
     U8,
@@ -158,8 +402,28 @@ enum TypeSpecifier {
     U32,
     S32,
     F32,
+    U64,
     S64,
     F64,
+    Bool,
+    /// A signed fixed-point number, e.g. 16.16
+    Fixed {
+        integer_bits: u8,
+        fraction_bits: u8,
+    },
+    /// Part of an unsigned integer, starting at a bit counted from the least significant
+    Bits {
+        base: Box<TypeSpecifier>,
+        shift: u8,
+        width: u8,
+    },
+    /// A value that's stored least significant byte first
+    LittleEndian(Box<TypeSpecifier>),
+    /// Text of at most a number of bytes, which ends early at a terminator
+    String {
+        encoding: StringEncoding,
+        size: u8,
+    },
     Bytes(u8),
 }
 
@@ -169,33 +433,133 @@ impl TypeSpecifier {
             Self::U8 | Self::S8 => mem::size_of::<u8>(),
             Self::U16 | Self::S16 => mem::size_of::<u16>(),
             Self::U32 | Self::S32 | Self::F32 => mem::size_of::<u32>(),
-            Self::S64 | Self::F64 => mem::size_of::<u64>(),
-            Self::Bytes(size) => *size as usize,
+            Self::U64 | Self::S64 | Self::F64 => mem::size_of::<u64>(),
+            Self::Bool => mem::size_of::<bool>(),
+            Self::Fixed {
+                integer_bits,
+                fraction_bits,
+            } => (integer_bits + fraction_bits) as usize / 8,
+            Self::Bits { base, .. } => base.size() as usize,
+            Self::LittleEndian(ty) => ty.size() as usize,
+            Self::String { size, .. } | Self::Bytes(size) => *size as usize,
         };
         size as u8
     }
+
+    fn from_name(name: &str) -> Option<Self> {
+        let ty = match name {
+            "u8" => Self::U8,
+            "s8" | "i8" => Self::S8,
+            "u16" => Self::U16,
+            "s16" | "i16" => Self::S16,
+            "u32" => Self::U32,
+            "s32" | "i32" => Self::S32,
+            "f32" => Self::F32,
+            "u64" => Self::U64,
+            "s64" | "i64" => Self::S64,
+            "f64" => Self::F64,
+            "bool" => Self::Bool,
+            _ => {
+                if let Some(name) = name.strip_suffix("le") {
+                    let ty = Self::from_name(name)?;
+                    return ty
+                        .has_byte_order()
+                        .then(|| Self::LittleEndian(Box::new(ty)));
+                }
+                if let Some(name) = name.strip_suffix("be") {
+                    return Self::from_name(name).filter(Self::has_byte_order);
+                }
+                let (integer_bits, fraction_bits) = name.strip_prefix("fx")?.split_once('.')?;
+                let (integer_bits, fraction_bits) =
+                    (integer_bits.parse().ok()?, fraction_bits.parse().ok()?);
+                if !matches!(integer_bits as u16 + fraction_bits as u16, 8 | 16 | 32 | 64) {
+                    return None;
+                }
+                Self::Fixed {
+                    integer_bits,
+                    fraction_bits,
+                }
+            }
+        };
+        Some(ty)
+    }
+
+    /// Whether this is a big-endian number that's more than one byte.
+    fn has_byte_order(&self) -> bool {
+        match self {
+            Self::U16 | Self::S16 | Self::U32 | Self::S32 | Self::F32 => true,
+            Self::U64 | Self::S64 | Self::F64 => true,
+            Self::Fixed { .. } => self.size() > 1,
+            _ => false,
+        }
+    }
+
+    fn is_unsigned_integer(&self) -> bool {
+        match self {
+            Self::U8 | Self::U16 | Self::U32 | Self::U64 => true,
+            Self::LittleEndian(ty) => ty.is_unsigned_integer(),
+            _ => false,
+        }
+    }
+
+    /// Parse a bit field, e.g. {bits="u32", shift=5, width=3}.
+    fn bits_from_table(table: &Table, lua: &Lua) -> mlua::Result<Self> {
+        let base = Self::from_lua(table.get("bits")?, lua)?;
+        let shift: Option<u8> = table.get("shift")?;
+        let shift = shift.unwrap_or_default();
+        let width: u8 = table.get("width")?;
+        let message = if !base.is_unsigned_integer() {
+            Some("bit fields must be in an unsigned integer")
+        } else if width == 0 || shift as u16 + width as u16 > 8 * base.size() as u16 {
+            Some("bit field is outside of the integer")
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            return Err(mlua::Error::FromLuaConversionError {
+                from: "table",
+                to: "TypeSpecifier".into(),
+                message: Some(message.into()),
+            });
+        }
+        Ok(Self::Bits {
+            base: Box::new(base),
+            shift,
+            width,
+        })
+    }
 }
 
 impl FromLua for TypeSpecifier {
-    fn from_lua(value: mlua::Value, _: &Lua) -> mlua::Result<Self> {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
         match value {
-            mlua::Value::String(string) => {
-                string.to_str().and_then(|string| match string.deref() {
-                    "u8" => Ok(Self::U8),
-                    "s8" | "i8" => Ok(Self::S8),
-                    "u16" => Ok(Self::U16),
-                    "s16" | "i16" => Ok(Self::S16),
-                    "u32" => Ok(Self::U32),
-                    "s32" | "i32" => Ok(Self::S32),
-                    "f32" => Ok(Self::F32),
-                    "s64" | "i64" => Ok(Self::S64),
-                    "f64" => Ok(Self::F64),
+            mlua::Value::String(string) => string.to_str().and_then(|string| {
+                Self::from_name(&string).ok_or_else(|| mlua::Error::FromLuaConversionError {
+                    from: "string",
+                    to: "TypeSpecifier".into(),
+                    message: None,
+                })
+            }),
+            mlua::Value::Table(table) if table.contains_key("bits")? => {
+                Self::bits_from_table(&table, lua)
+            }
+            mlua::Value::Table(table) => {
+                // A string type, e.g. {sjis=32}
+                let mut pairs = table.pairs::<String, u8>();
+                match (pairs.next().transpose()?, pairs.next()) {
+                    (Some((name, size)), None) => StringEncoding::from_name(&name)
+                        .map(|encoding| Self::String { encoding, size })
+                        .ok_or_else(|| mlua::Error::FromLuaConversionError {
+                            from: "table",
+                            to: "TypeSpecifier".into(),
+                            message: Some(format!("unknown string encoding {}", name)),
+                        }),
                     _ => Err(mlua::Error::FromLuaConversionError {
-                        from: "string",
+                        from: "table",
                         to: "TypeSpecifier".into(),
                         message: None,
                     }),
-                })
+                }
             }
             mlua::Value::Integer(size) => TryInto::<u8>::try_into(size)
                 .map_err(|err| mlua::Error::FromLuaConversionError {
@@ -213,10 +577,7 @@ impl FromLua for TypeSpecifier {
     }
 }
 
-fn read_tuple_from_table(
-    table: mlua::Table,
-    lua: &Lua,
-) -> mlua::Result<(u32, TypeSpecifier, Option<i16>)> {
+fn read_tuple_from_table(table: mlua::Table, lua: &Lua) -> mlua::Result<ReadTuple> {
     FromLuaMulti::from_lua_multi(
         {
             let address: mlua::Value = table.get(1)?;
@@ -229,33 +590,421 @@ fn read_tuple_from_table(
     )
 }
 
+/// A change made to the variables through a VariableStore.
+#[derive(Debug, Clone)]
+pub enum VariableOperation {
+    Write(String, mlua::Result<JsonValue>),
+    Delete(String),
+    ClearPrefix(String),
+}
+
+/// What a run of the game watcher produced.
+#[derive(Debug, Clone)]
+pub struct GameWatcherUpdate {
+    pub operations: Vec<VariableOperation>,
+    /// The watcher marked the game as loading, so policies with IgnoreNull hold back nulls.
+    pub loading: bool,
+}
+
+/// What a run of a custom command's handler produced.
+#[derive(Debug, Clone)]
+pub struct CommandUpdate {
+    pub operations: Vec<VariableOperation>,
+    /// A Reply with the handler's return value, if it wasn't nil.
+    pub reply: Option<ServerCommand>,
+}
+
+type ReadTuple = (u32, TypeSpecifier, Option<i16>);
+
+fn read_list_from_tables(tables: Vec<Table>, lua: &Lua) -> mlua::Result<Vec<ReadTuple>> {
+    tables
+        .into_iter()
+        .map(|table| read_tuple_from_table(table, lua))
+        .collect()
+}
+
+/// Perform a list of reads and convert the results, as GameCube:Read() does.
+fn read_batch(
+    lua: &Lua,
+    connection: &dyn GameCubeConnection,
+    read_list: Vec<ReadTuple>,
+) -> mlua::Result<Vec<mlua::Value>> {
+    let (read_list, type_specifiers) = {
+        let mut reads = Vec::with_capacity(read_list.len());
+        let mut types = Vec::with_capacity(read_list.len());
+        for (addr, ty, offset) in read_list {
+            reads.push(Read::from_parts(addr, ty.size(), offset));
+            types.push(ty);
+        }
+        (reads, types)
+    };
+    let byte_arrays = connection.read(&read_list)?;
+    Iterator::zip(byte_arrays.into_iter(), type_specifiers)
+        .map(|(bytes, type_specifier)| convert_bytes(lua, bytes, &type_specifier))
+        .collect::<mlua::Result<Vec<mlua::Value>>>()
+}
+
+/// The type of a field in a struct defined with GameCube:DefineStruct().
+#[derive(Debug, Clone)]
+enum FieldType {
+    Value(TypeSpecifier),
+    Struct(Rc<StructDefinition>),
+    Array {
+        element: Box<FieldType>,
+        count: u32,
+        stride: u32,
+    },
+}
+
+impl FieldType {
+    /// Parse a field type, which is a TypeSpecifier, the name of a struct defined earlier, or a
+    /// table describing an array of either.
+    fn from_lua(
+        value: mlua::Value,
+        lua: &Lua,
+        structs: &HashMap<String, Rc<StructDefinition>>,
+    ) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Table(table) if table.contains_key("array")? => {
+                let element = Self::from_lua(table.get("array")?, lua, structs)?;
+                let count: u32 = table.get("count")?;
+                let stride: Option<u32> = table.get("stride")?;
+                Ok(Self::Array {
+                    stride: stride.unwrap_or_else(|| element.size()),
+                    element: Box::new(element),
+                    count,
+                })
+            }
+            mlua::Value::String(ref name) => {
+                let name = name.to_str()?.to_owned();
+                match structs.get(&name) {
+                    Some(definition) => Ok(Self::Struct(Rc::clone(definition))),
+                    None => TypeSpecifier::from_lua(value, lua)
+                        .map(Self::Value)
+                        .map_err(|_| mlua::Error::runtime(format!("unknown type {}", name))),
+                }
+            }
+            value => TypeSpecifier::from_lua(value, lua).map(Self::Value),
+        }
+    }
+
+    fn size(&self) -> u32 {
+        match self {
+            Self::Value(ty) => ty.size() as u32,
+            Self::Struct(definition) => definition.size,
+            Self::Array { count, stride, .. } => count.saturating_mul(*stride),
+        }
+    }
+
+    /// Number of values read for this field.
+    fn value_count(&self) -> u64 {
+        match self {
+            Self::Value(_) => 1,
+            Self::Struct(definition) => definition
+                .fields
+                .iter()
+                .map(|field| field.field_type.value_count())
+                .fold(0, u64::saturating_add),
+            Self::Array { element, count, .. } => {
+                element.value_count().saturating_mul(*count as u64)
+            }
+        }
+    }
+
+    /// List the values to read for this field at an offset from the start of the read.
+    fn collect_reads(&self, offset: i64, reads: &mut Vec<(i64, TypeSpecifier)>) {
+        match self {
+            Self::Value(ty) => reads.push((offset, ty.clone())),
+            Self::Struct(definition) => {
+                for field in &definition.fields {
+                    field
+                        .field_type
+                        .collect_reads(offset + field.offset as i64, reads);
+                }
+            }
+            Self::Array {
+                element,
+                count,
+                stride,
+            } => {
+                for i in 0..*count {
+                    element.collect_reads(offset + (i as i64) * (*stride as i64), reads);
+                }
+            }
+        }
+    }
+
+    /// Build the Lua value for this field from the results of the reads listed by collect_reads().
+    fn build(
+        &self,
+        lua: &Lua,
+        results: &mut impl Iterator<Item = Option<Vec<u8>>>,
+    ) -> mlua::Result<mlua::Value> {
+        match self {
+            Self::Value(ty) => convert_bytes(lua, results.next().flatten(), ty),
+            Self::Struct(definition) => {
+                let table = lua.create_table()?;
+                for field in &definition.fields {
+                    table.set(field.name.as_str(), field.field_type.build(lua, results)?)?;
+                }
+                Ok(mlua::Value::Table(table))
+            }
+            Self::Array { element, count, .. } => {
+                let table = lua.create_table()?;
+                for i in 1..=*count {
+                    table.set(i, element.build(lua, results)?)?;
+                }
+                Ok(mlua::Value::Table(table))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StructField {
+    name: String,
+    offset: u32,
+    field_type: FieldType,
+}
+
+/// A struct defined with GameCube:DefineStruct(), read all at once with GameCube:ReadStruct().
+#[derive(Debug, Clone)]
+struct StructDefinition {
+    fields: Vec<StructField>,
+    /// Distance between elements in arrays of the struct
+    size: u32,
+}
+
+impl StructDefinition {
+    fn from_lua(
+        name: &str,
+        fields: Vec<Table>,
+        size: Option<u32>,
+        lua: &Lua,
+        structs: &HashMap<String, Rc<StructDefinition>>,
+    ) -> mlua::Result<Self> {
+        let fields = fields
+            .into_iter()
+            .map(|field| {
+                let field_name: String = field.get(1)?;
+                let offset: u32 = field.get(2)?;
+                let field_type =
+                    FieldType::from_lua(field.get(3)?, lua, structs).map_err(|err| {
+                        let message = match err {
+                            mlua::Error::RuntimeError(message) => message,
+                            err => err.to_string(),
+                        };
+                        mlua::Error::runtime(format!("{}.{}: {}", name, field_name, message))
+                    })?;
+                Ok(StructField {
+                    name: field_name,
+                    offset,
+                    field_type,
+                })
+            })
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let size = size.unwrap_or_else(|| {
+            fields
+                .iter()
+                .map(|field| field.offset.saturating_add(field.field_type.size()))
+                .max()
+                .unwrap_or_default()
+        });
+        let values = fields
+            .iter()
+            .map(|field| field.field_type.value_count())
+            .fold(0, u64::saturating_add);
+        if values > MAX_STRUCT_VALUES {
+            return Err(mlua::Error::runtime(format!(
+                "{} has more than {} values",
+                name, MAX_STRUCT_VALUES
+            )));
+        }
+        Ok(Self { fields, size })
+    }
+
+    /// Read the struct at an address, or at an offset from the pointer at an address, with one
+    /// batch of reads.
+    fn read(
+        self: &Rc<Self>,
+        lua: &Lua,
+        connection: &dyn GameCubeConnection,
+        address: u32,
+        pointer_offset: Option<i16>,
+    ) -> mlua::Result<mlua::Value> {
+        let mut offsets = Vec::new();
+        let field_type = FieldType::Struct(Rc::clone(self));
+        field_type.collect_reads(pointer_offset.unwrap_or_default() as i64, &mut offsets);
+        let reads = offsets
+            .into_iter()
+            .map(|(offset, ty)| match pointer_offset {
+                None => u32::try_from(address as i64 + offset)
+                    .map(|address| Read::address(address, ty.size()))
+                    .map_err(|_| mlua::Error::runtime("struct extends past the end of memory")),
+                Some(_) => i16::try_from(offset)
+                    .map(|offset| Read::pointer(address, offset, ty.size()))
+                    .map_err(|_| {
+                        mlua::Error::runtime("struct is too large to read from a pointer")
+                    }),
+            })
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let results = connection.read(&reads)?;
+        field_type.build(lua, &mut results.into_iter())
+    }
+}
+
+#[derive(Debug, Default)]
+struct VariableStoreState {
+    operations: Vec<VariableOperation>,
+    /// Number of operations before the open transaction, if there is one
+    transaction_start: Option<usize>,
+    unstable: bool,
+    loading: bool,
+}
+
 #[derive(Clone)]
-struct VariableStore(Rc<RefCell<Vec<(String, mlua::Result<JsonValue>)>>>);
+struct VariableStore(Rc<RefCell<VariableStoreState>>);
 
 impl VariableStore {
-    fn new(lua: &Lua) -> mlua::Result<(Self, Table)> {
+    /// Create a store, and the Lua table scripts write to it with. Written values are normalized
+    /// and checked against the schema.
+    fn new(
+        lua: &Lua,
+        schema: Rc<Schema>,
+        normalization: Normalization,
+    ) -> mlua::Result<(Self, Table)> {
         let table = lua.create_table()?;
 
-        let storage = Rc::new(RefCell::new(vec![]));
+        let storage = Rc::new(RefCell::new(VariableStoreState::default()));
 
         let store = Self(Rc::clone(&storage));
 
+        let state = Rc::clone(&storage);
         table.set(
             "WriteVariable",
             lua.create_function(
                 move |lua, (_, key, value): (mlua::Value, mlua::Value, mlua::Value)| {
                     let key = convert_lua_to_string(lua, &key)?;
-                    let value = convert_lua_to_json(lua, &value);
-                    Ok(storage.borrow_mut().push((key, value)))
+                    let value = match convert_lua_to_json(lua, &value, &normalization) {
+                        Ok(value) if !is_writable(&key, &value) => Err(mlua::Error::runtime(
+                            "only a table with named members can be written at the root",
+                        )),
+                        Ok(value) => {
+                            let (value, violations) = schema.validate(&key, value);
+                            if !violations.is_empty() {
+                                let location = script_location(lua);
+                                for violation in violations {
+                                    eprintln!("{}: {}", location, violation);
+                                }
+                            }
+                            match value {
+                                Some(value) => Ok(value),
+                                None => return Ok(()),
+                            }
+                        }
+                        Err(err) => Err(err),
+                    };
+                    state
+                        .borrow_mut()
+                        .operations
+                        .push(VariableOperation::Write(key, value));
+                    Ok(())
                 },
             )?,
         )?;
+        let state = Rc::clone(&storage);
+        table.set(
+            "DeleteVariable",
+            lua.create_function(move |lua, (_, key): (mlua::Value, mlua::Value)| {
+                let key = convert_lua_to_string(lua, &key)?;
+                state
+                    .borrow_mut()
+                    .operations
+                    .push(VariableOperation::Delete(key));
+                Ok(())
+            })?,
+        )?;
+        let state = Rc::clone(&storage);
+        table.set(
+            "ClearPrefix",
+            lua.create_function(move |lua, (_, prefix): (mlua::Value, mlua::Value)| {
+                let prefix = convert_lua_to_string(lua, &prefix)?;
+                state
+                    .borrow_mut()
+                    .operations
+                    .push(VariableOperation::ClearPrefix(prefix));
+                Ok(())
+            })?,
+        )?;
+
+        let state = Rc::clone(&storage);
+        table.set(
+            "Begin",
+            lua.create_function(move |_, _: mlua::Value| {
+                let mut state = state.borrow_mut();
+                if state.transaction_start.is_some() {
+                    return Err(mlua::Error::runtime("a transaction is already open"));
+                }
+                state.transaction_start = Some(state.operations.len());
+                Ok(())
+            })?,
+        )?;
+        let state = Rc::clone(&storage);
+        table.set(
+            "Commit",
+            lua.create_function(move |_, _: mlua::Value| {
+                match state.borrow_mut().transaction_start.take() {
+                    Some(_) => Ok(()),
+                    None => Err(mlua::Error::runtime("no transaction is open")),
+                }
+            })?,
+        )?;
+        let state = Rc::clone(&storage);
+        table.set(
+            "Abort",
+            lua.create_function(move |_, _: mlua::Value| {
+                let mut state = state.borrow_mut();
+                match state.transaction_start.take() {
+                    Some(start) => {
+                        state.operations.truncate(start);
+                        Ok(())
+                    }
+                    None => Err(mlua::Error::runtime("no transaction is open")),
+                }
+            })?,
+        )?;
+        let state = Rc::clone(&storage);
+        table.set(
+            "MarkUnstable",
+            lua.create_function(move |_, _: mlua::Value| {
+                state.borrow_mut().unstable = true;
+                Ok(())
+            })?,
+        )?;
+        table.set(
+            "MarkLoading",
+            lua.create_function(move |_, _: mlua::Value| {
+                storage.borrow_mut().loading = true;
+                Ok(())
+            })?,
+        )?;
 
         Ok((store, table))
     }
 
-    fn unwrap(self) -> Vec<(String, mlua::Result<JsonValue>)> {
-        self.0.borrow().clone()
+    /// Get the operations to apply. Nothing is applied if the store was marked unstable, and a
+    /// transaction left open is aborted.
+    fn unwrap(self) -> Vec<VariableOperation> {
+        let state = self.0.borrow();
+        if state.unstable {
+            return Vec::new();
+        }
+        let end = state.transaction_start.unwrap_or(state.operations.len());
+        state.operations[..end].to_vec()
+    }
+
+    fn is_loading(&self) -> bool {
+        self.0.borrow().loading
     }
 }
 
@@ -272,6 +1021,16 @@ impl GameInterface {
         table.set("Slots", mlua::Value::Nil)?;
         table.set("VerifyFunc", mlua::Value::Nil)?;
         table.set("GameWatcher", mlua::Value::Nil)?;
+        table.set("Commands", mlua::Value::Nil)?;
+        table.set("PersistentData", lua.create_table()?)?;
+        table.set("VariablePolicies", mlua::Value::Nil)?;
+        table.set("Derived", mlua::Value::Nil)?;
+        table.set("Schema", mlua::Value::Nil)?;
+        table.set("SchemaStrictness", mlua::Value::Nil)?;
+        table.set("Normalization", mlua::Value::Nil)?;
+        table.set("Priority", mlua::Value::Nil)?;
+        table.set("GameIDs", mlua::Value::Nil)?;
+        table.set("Revisions", mlua::Value::Nil)?;
 
         Ok(table)
     }
@@ -284,21 +1043,106 @@ impl GameInterface {
         self.0.get("Version")
     }
 
-    #[allow(unused)]
     pub fn features(&self) -> mlua::Result<Option<Vec<String>>> {
         self.0.get("Features")
     }
 
-    #[allow(unused)]
     pub fn slots(&self) -> mlua::Result<Option<Vec<String>>> {
         self.0.get("Slots")
     }
 
-    fn verify(&self) -> mlua::Result<bool> {
+    /// Interfaces with a higher priority are chosen over others that also match the game.
+    pub fn priority(&self) -> mlua::Result<i64> {
+        let priority: Option<i64> = self.0.get("Priority")?;
+        Ok(priority.unwrap_or_default())
+    }
+
+    pub fn variable_policies(&self) -> mlua::Result<HashMap<String, VariablePolicy>> {
+        let policies: Option<HashMap<String, VariablePolicy>> = self.0.get("VariablePolicies")?;
+        Ok(policies.unwrap_or_default())
+    }
+
+    pub fn derived_variables(&self) -> mlua::Result<Vec<DerivedVariable>> {
+        let derived: Option<HashMap<String, Table>> = self.0.get("Derived")?;
+        derived
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, table)| {
+                let dependencies: Option<Vec<String>> = table.get("Deps")?;
+                Ok(DerivedVariable {
+                    name: normalize_path(&name),
+                    dependencies: dependencies
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|dependency| normalize_path(dependency))
+                        .collect(),
+                    function: table.get("Func")?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn schema(&self) -> mlua::Result<Schema> {
+        let entries: Option<HashMap<String, VariableSchema>> = self.0.get("Schema")?;
+        let strictness: Option<Strictness> = self.0.get("SchemaStrictness")?;
+        Ok(Schema::new(
+            entries.unwrap_or_default(),
+            strictness.unwrap_or_default(),
+        ))
+    }
+
+    pub fn normalization(&self) -> mlua::Result<Normalization> {
+        let normalization: Option<Normalization> = self.0.get("Normalization")?;
+        Ok(normalization.unwrap_or_default())
+    }
+
+    /// Get the handler for a custom command, if there is one and it is listed in Features.
+    fn command_handler(&self, name: &str) -> mlua::Result<Option<Function>> {
+        let features = self.features()?.unwrap_or_default();
+        if !features.iter().any(|feature| feature == name) {
+            return Ok(None);
+        }
+        let commands: Option<Table> = self.0.get("Commands")?;
+        match commands {
+            Some(commands) => commands.get(name),
+            None => Ok(None),
+        }
+    }
+
+    fn run_command(
+        &self,
+        handler: &Function,
+        arguments: mlua::Value,
+        store: &Table,
+    ) -> mlua::Result<mlua::Value> {
+        handler.call((&self.0, arguments, store))
+    }
+
+    /// Check the disc header against the GameIDs and Revisions the interface declares. Returns None
+    /// if it doesn't declare any game IDs.
+    fn matches_disc(&self, header: Option<&DiscHeader>) -> mlua::Result<Option<bool>> {
+        let game_ids: Option<Vec<String>> = self.0.get("GameIDs")?;
+        let revisions: Option<Vec<u8>> = self.0.get("Revisions")?;
+        let Some(game_ids) = game_ids else {
+            return Ok(None);
+        };
+        Ok(Some(header.is_some_and(|header| {
+            game_ids.contains(&header.game_id)
+                && revisions.is_none_or(|revisions| revisions.contains(&header.revision))
+        })))
+    }
+
+    /// Check whether the interface can track the running game. Interfaces that declare GameIDs
+    /// are matched against the disc header first, and only need a VerifyFunc for extra checks.
+    fn verify(&self, header: Option<&DiscHeader>) -> mlua::Result<bool> {
+        let matches_disc = self.matches_disc(header)?;
+        if matches_disc == Some(false) {
+            return Ok(false);
+        }
         let verify_func: mlua::Value = self.0.get("VerifyFunc")?;
         let verify_func = match verify_func.as_function() {
             Some(f) => f,
-            None => return Ok(false),
+            None => return Ok(matches_disc.is_some()),
         };
         Ok(coerce_boolean(&verify_func.call((&self.0,))?))
     }
@@ -314,15 +1158,212 @@ impl GameInterface {
     }
 }
 
+/// A variable computed by a Lua function from the values of other variables.
+#[derive(Clone)]
+pub struct DerivedVariable {
+    pub name: String,
+    /// Paths of the variables it is computed from. A change at or beneath any of them causes the
+    /// variable to be computed again.
+    pub dependencies: Vec<String>,
+    function: Function,
+}
+
+/// An error raised by a custom command handler to be sent back to the client.
+#[derive(Debug, Clone)]
+struct CommandError {
+    reason: ErrorReplyReason,
+    argument: Option<String>,
+    description: Option<String>,
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.reason.fmt(f)?;
+        if let Some(argument) = &self.argument {
+            write!(f, " ({})", argument)?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, ": {}", description)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for CommandError {}
+
+impl FromLua for ErrorReplyReason {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        match String::from_lua(value, lua)?.as_str() {
+            "MissingArgument" => Ok(Self::MissingArgument),
+            "BadValue" => Ok(Self::BadValue),
+            "Unknown" => Ok(Self::Unknown),
+            reason => Err(mlua::Error::FromLuaConversionError {
+                from: "string",
+                to: "ErrorReplyReason".into(),
+                message: Some(format!("invalid reason {}", reason)),
+            }),
+        }
+    }
+}
+
+impl FromLua for VariablePolicy {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        let table = Table::from_lua(value, lua)?;
+        let seconds = |key| -> mlua::Result<Duration> {
+            let seconds: Option<f64> = table.get(key)?;
+            Duration::try_from_secs_f64(seconds.unwrap_or(0.0)).map_err(|err| {
+                mlua::Error::FromLuaConversionError {
+                    from: "number",
+                    to: "Duration".into(),
+                    message: Some(format!("invalid {}: {}", key, err)),
+                }
+            })
+        };
+        Ok(Self {
+            stable_for: seconds("StableFor")?,
+            ignore_null: coerce_boolean(&table.get("IgnoreNull")?),
+            monotonic: coerce_boolean(&table.get("Monotonic")?),
+            min_interval: seconds("MinInterval")?,
+        })
+    }
+}
+
+impl FromLua for VariableSchema {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        let table = Table::from_lua(value, lua)?;
+        let value_type = match table.get::<Option<String>>("Type")? {
+            Some(name) => Some(SchemaType::from_name(&name).ok_or_else(|| {
+                mlua::Error::FromLuaConversionError {
+                    from: "string",
+                    to: "SchemaType".into(),
+                    message: Some(format!("unknown type {}", name)),
+                }
+            })?),
+            None => None,
+        };
+        Ok(Self {
+            value_type,
+            min: table.get("Min")?,
+            max: table.get("Max")?,
+            values: table.get("Values")?,
+        })
+    }
+}
+
+impl FromLua for Strictness {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        let name = String::from_lua(value, lua)?;
+        match name.as_str() {
+            "Warn" => Ok(Self::Warn),
+            "Coerce" => Ok(Self::Coerce),
+            "Reject" => Ok(Self::Reject),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: "string",
+                to: "Strictness".into(),
+                message: Some(format!("unknown strictness {}", name)),
+            }),
+        }
+    }
+}
+
+impl FromLua for Normalization {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        let table = Table::from_lua(value, lua)?;
+        let invalid = |key: &str, name: &str| mlua::Error::FromLuaConversionError {
+            from: "string",
+            to: "Normalization".into(),
+            message: Some(format!("unknown {} {}", key, name)),
+        };
+        let large_integers = match table.get::<Option<String>>("LargeIntegers")?.as_deref() {
+            None | Some("String") => LargeIntegers::String,
+            Some("Number") => LargeIntegers::Number,
+            Some(name) => Err(invalid("LargeIntegers", name))?,
+        };
+        let non_finite = match table.get::<Option<String>>("NonFinite")?.as_deref() {
+            None | Some("Null") => NonFinite::Null,
+            Some("Error") => NonFinite::Error,
+            Some(name) => Err(invalid("NonFinite", name))?,
+        };
+        Ok(Self {
+            large_integers,
+            non_finite,
+            float_precision: table.get("FloatPrecision")?,
+        })
+    }
+}
+
 impl FromLua for GameInterface {
     fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
         Ok(Self(Table::from_lua(value, lua)?))
     }
 }
 
+/// The part of the disc header that identifies the running game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DiscHeader {
+    /// The console, game, region and maker codes, e.g. GM8E01
+    game_id: String,
+    disc_number: u8,
+    revision: u8,
+    audio_streaming: bool,
+    stream_buffer_size: u8,
+}
+
+impl DiscHeader {
+    /// Returns None if the header couldn't be read, e.g. because there's no disc.
+    fn read(connection: &dyn GameCubeConnection) -> io::Result<Option<Self>> {
+        let bytes = connection.read_single(Read::address(GCN_BASE_ADDRESS, DISC_HEADER_SIZE))?;
+        Ok(bytes.and_then(|bytes| {
+            Some(Self {
+                game_id: String::from_utf8_lossy(bytes.get(..6)?).into_owned(),
+                disc_number: *bytes.get(6)?,
+                revision: *bytes.get(7)?,
+                audio_streaming: *bytes.get(8)? != 0,
+                stream_buffer_size: *bytes.get(9)?,
+            })
+        }))
+    }
+}
+
+impl IntoLua for DiscHeader {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+        let code = |range: Range<usize>| self.game_id.get(range).map(str::to_owned);
+        let region_code = code(3..4);
+        let region = match region_code.as_deref() {
+            Some("J") => Some("Japan"),
+            Some("E") => Some("USA"),
+            Some("P") => Some("Europe"),
+            Some("D") => Some("Germany"),
+            Some("F") => Some("France"),
+            Some("S") => Some("Spain"),
+            Some("I") => Some("Italy"),
+            Some("U") => Some("Australia"),
+            Some("K") => Some("Korea"),
+            _ => None,
+        };
+        table.set("GameCode", code(0..4))?;
+        table.set("MakerCode", code(4..6))?;
+        table.set("RegionCode", region_code)?;
+        table.set("Region", region)?;
+        table.set("GameID", self.game_id)?;
+        table.set("DiscNumber", self.disc_number)?;
+        table.set("Revision", self.revision)?;
+        table.set("AudioStreaming", self.audio_streaming)?;
+        table.set("StreamBufferSize", self.stream_buffer_size)?;
+        Ok(mlua::Value::Table(table))
+    }
+}
+
 struct LuaGcnConnection {
     gamecube_connection: Box<dyn GameCubeConnection>,
+    /// Header of the disc that was running when the interface was chosen
+    disc_header: Option<DiscHeader>,
     game_interface: Option<GameInterface>,
+    /// Name the game interface was registered under
+    interface_name: Option<String>,
+    schema: Rc<Schema>,
+    normalization: Normalization,
 }
 
 impl LuaGcnConnection {
@@ -332,43 +1373,188 @@ impl LuaGcnConnection {
     ) -> Self {
         Self {
             gamecube_connection: gamecube,
+            disc_header: None,
             game_interface,
+            interface_name: None,
+            schema: Default::default(),
+            normalization: Default::default(),
         }
     }
 }
 
-pub struct LuaInterface {
-    lua: Lua,
-    game_interfaces: Rc<RefCell<HashMap<String, GameInterface>>>,
-    connection: Rc<RefCell<Option<LuaGcnConnection>>>,
+/// A game interface, and the script that registered it.
+struct RegisteredInterface {
+    script: PathBuf,
+    /// Position in the order interfaces were registered, to break ties between priorities
+    order: usize,
+    interface: GameInterface,
 }
 
-impl LuaInterface {
-    pub fn new() -> mlua::Result<Self> {
-        let lua = Lua::new();
-        let connection: Rc<RefCell<Option<LuaGcnConnection>>> = Rc::new(RefCell::new(None));
+/// Budgets for each call into a script, so a script stuck in a loop can't hang the bridge.
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptLimits {
+    /// Time a call can take, including time spent reading memory.
+    pub time: Duration,
+    /// Memory a call can allocate, in bytes.
+    pub memory: usize,
+}
+
+/// Create a hook that raises an error once a call into a script is past its deadline, and keeps
+/// raising it until the call returns so that the script can't carry on by catching it.
+fn limit_hook(
+    deadline: Rc<Cell<Option<Instant>>>,
+    tripped: Rc<Cell<bool>>,
+    limit: Duration,
+) -> impl Fn(&Lua, mlua::Debug) -> mlua::Result<VmState> {
+    move |_, _| {
+        if deadline
+            .get()
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            tripped.set(true);
+        }
+        if tripped.get() {
+            Err(mlua::Error::external(LimitExceeded::Time(limit)))
+        } else {
+            Ok(VmState::Continue)
+        }
+    }
+}
+
+/// An error for a call into a script that went over its limits.
+#[derive(Debug, Clone)]
+enum LimitExceeded {
+    Time(Duration),
+    Memory(usize),
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time(limit) => write!(f, "script took longer than {:?}", limit),
+            Self::Memory(limit) => write!(f, "script used more than {} bytes of memory", limit),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+pub struct LuaInterface {
+    lua: Lua,
+    limits: ScriptLimits,
+    /// When the current call into a script runs out of time
+    deadline: Rc<Cell<Option<Instant>>>,
+    /// Set once the current call into a script runs out of time, after which the hook keeps
+    /// raising the error until the call returns
+    tripped: Rc<Cell<bool>>,
+    /// Number of times in a row each interface has gone over its limits
+    limit_failures: RefCell<HashMap<String, u32>>,
+    disabled_interfaces: RefCell<HashSet<String>>,
+    game_interfaces: Rc<RefCell<HashMap<String, RegisteredInterface>>>,
+    /// Number of interfaces registered so far
+    registrations: Rc<Cell<usize>>,
+    connection: Rc<RefCell<Option<LuaGcnConnection>>>,
+}
+
+impl LuaInterface {
+    pub fn new(limits: ScriptLimits) -> mlua::Result<Self> {
+        let lua = Lua::new();
+        // Strings share one metatable, whose __index is the real string library
+        lua.load(r#"getmetatable("").__metatable = false"#).exec()?;
+        let deadline: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
+        let tripped = Rc::new(Cell::new(false));
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(LIMIT_CHECK_INTERVAL),
+            limit_hook(Rc::clone(&deadline), Rc::clone(&tripped), limits.time),
+        );
+        let connection: Rc<RefCell<Option<LuaGcnConnection>>> = Rc::new(RefCell::new(None));
         let game_interfaces = Rc::new(RefCell::new(HashMap::new()));
 
-        let script_host = lua.create_table()?;
-        script_host.set(
-            "CreateGameInterface",
-            lua.create_function(|lua, (_,): (mlua::Value,)| GameInterface::create_table(lua))?,
+        Ok(Self {
+            lua,
+            limits,
+            deadline,
+            tripped,
+            limit_failures: RefCell::new(HashMap::new()),
+            disabled_interfaces: RefCell::new(HashSet::new()),
+            game_interfaces,
+            registrations: Rc::new(Cell::new(0)),
+            connection,
+        })
+    }
+
+    /// Create the GameCube table for a script, with functions to read the console's memory.
+    fn create_gamecube(&self) -> mlua::Result<Table> {
+        let lua = &self.lua;
+        let connection = Rc::clone(&self.connection);
+        // Structs defined by the script
+        let structs: Rc<RefCell<HashMap<String, Rc<StructDefinition>>>> =
+            Rc::new(RefCell::new(HashMap::new()));
+        let gamecube = lua.create_table()?;
+        gamecube.set("GameIDAddress", GCN_BASE_ADDRESS)?;
+        for (name, address) in LOW_MEMORY_GLOBALS {
+            gamecube.set(*name, *address)?;
+        }
+        let defined_structs = Rc::clone(&structs);
+        gamecube.set(
+            "DefineStruct",
+            lua.create_function(
+                move |lua, (_, name, fields, size): (mlua::Value, String, Vec<Table>, Option<u32>)| {
+                    let definition = {
+                        let structs = defined_structs.borrow();
+                        StructDefinition::from_lua(&name, fields, size, lua, &structs)?
+                    };
+                    defined_structs
+                        .borrow_mut()
+                        .insert(name, Rc::new(definition));
+                    Ok(())
+                },
+            )?,
         )?;
-        let interfaces = Rc::clone(&game_interfaces);
-        script_host.set(
-            "AddGameInterface",
+        let connect = Rc::clone(&connection);
+        // The reads for a struct are listed outside of Lua, where the memory limit doesn't apply
+        let memory_limit = self.limits.memory;
+        let max_values =
+            memory_limit / (mem::size_of::<(i64, TypeSpecifier)>() + mem::size_of::<Read>());
+        gamecube.set(
+            "ReadStruct",
             lua.create_function(
-                move |_, (_, name, value): (mlua::Value, String, GameInterface)| {
-                    Ok({
-                        interfaces.borrow_mut().insert(name, value);
-                    })
+                move |lua, (_, address, name, offset): (mlua::Value, u32, String, Option<i16>)| {
+                    let definition =
+                        structs.borrow().get(&name).cloned().ok_or_else(|| {
+                            mlua::Error::runtime(format!("unknown struct {}", name))
+                        })?;
+                    if FieldType::Struct(Rc::clone(&definition)).value_count() > max_values as u64 {
+                        return Err(mlua::Error::external(LimitExceeded::Memory(memory_limit)));
+                    }
+                    let connection = connect.borrow();
+                    let connection = connection
+                        .as_ref()
+                        .ok_or(io::Error::from(io::ErrorKind::NotConnected))?;
+                    definition.read(
+                        lua,
+                        connection.gamecube_connection.as_ref(),
+                        address,
+                        offset,
+                    )
                 },
             )?,
         )?;
-        lua.globals().set("ScriptHost", script_host)?;
-
-        let gamecube = lua.create_table()?;
-        gamecube.set("GameIDAddress", GCN_BASE_ADDRESS)?;
+        let connect = Rc::clone(&connection);
+        gamecube.set(
+            "GetDiscInfo",
+            lua.create_function(move |_, _: mlua::Value| {
+                let connection = connect.borrow();
+                let connection = connection
+                    .as_ref()
+                    .ok_or(io::Error::from(io::ErrorKind::NotConnected))?;
+                // The header is read once per connection, and only read here if that failed
+                match &connection.disc_header {
+                    Some(header) => Ok(Some(header.clone())),
+                    None => Ok(DiscHeader::read(connection.gamecube_connection.as_ref())?),
+                }
+            })?,
+        )?;
         let connect = Rc::clone(&connection);
         gamecube.set(
             "ReadSingle",
@@ -399,39 +1585,376 @@ impl LuaInterface {
                 let connection = connection
                     .as_ref()
                     .ok_or(io::Error::from(io::ErrorKind::NotConnected))?;
-                let read_list = read_list
-                    .into_iter()
-                    .map(|table| read_tuple_from_table(table, lua))
-                    .collect::<mlua::Result<Vec<_>>>()?;
-                let (read_list, type_specifiers) = {
-                    let mut reads = Vec::with_capacity(read_list.len());
-                    let mut types = Vec::with_capacity(read_list.len());
-                    for (addr, ty, offset) in read_list {
-                        reads.push(Read::from_parts(addr, ty.size(), offset));
-                        types.push(ty);
-                    }
-                    (reads, types)
-                };
-                let byte_arrays = connection.gamecube_connection.read(&read_list)?;
-                Iterator::zip(byte_arrays.into_iter(), type_specifiers.into_iter())
-                    .map(|(bytes, type_specifier)| convert_bytes(lua, bytes, &type_specifier))
-                    .collect::<mlua::Result<Vec<mlua::Value>>>()
+                let read_list = read_list_from_tables(read_list, lua)?;
+                read_batch(lua, connection.gamecube_connection.as_ref(), read_list)
             })?,
         )?;
-        lua.globals().set("GameCube", gamecube)?;
 
-        Ok(Self {
-            lua,
-            game_interfaces,
-            connection,
+        Ok(gamecube)
+    }
+
+    /// Call into a script with the time and memory limits applied.
+    fn limited<R>(&self, call: impl FnOnce() -> mlua::Result<R>) -> mlua::Result<R> {
+        self.deadline.set(Some(Instant::now() + self.limits.time));
+        self.tripped.set(false);
+        let previous_memory_limit = self
+            .lua
+            .set_memory_limit(self.lua.used_memory() + self.limits.memory);
+        let result = call();
+        self.deadline.set(None);
+        self.tripped.set(false);
+        if let Ok(limit) = previous_memory_limit {
+            self.lua.set_memory_limit(limit).ok();
+        }
+        result.map_err(|err| {
+            let out_of_memory = err.chain().any(|err| {
+                matches!(
+                    err.downcast_ref::<mlua::Error>(),
+                    Some(mlua::Error::MemoryError(_))
+                )
+            });
+            if out_of_memory || matches!(err, mlua::Error::MemoryError(_)) {
+                mlua::Error::external(LimitExceeded::Memory(self.limits.memory))
+            } else {
+                err
+            }
         })
     }
 
-    pub fn run_script(&self, path: impl AsRef<Path>) -> mlua::Result<()> {
+    /// Call into an interface with the limits applied, disabling it if it goes over them too many
+    /// times without the game watcher completing in between.
+    fn limited_interface<R>(
+        &self,
+        name: Option<&str>,
+        call: impl FnOnce() -> mlua::Result<R>,
+    ) -> mlua::Result<R> {
+        let result = self.limited(call);
+        let Some(name) = name else {
+            return result;
+        };
+        let exceeded = result.as_ref().is_err_and(|err| {
+            err.chain()
+                .any(|err| err.downcast_ref::<LimitExceeded>().is_some())
+        });
+        if exceeded {
+            let mut limit_failures = self.limit_failures.borrow_mut();
+            let failures = limit_failures.entry(name.to_owned()).or_default();
+            *failures += 1;
+            if *failures >= MAX_LIMIT_FAILURES {
+                eprintln!(
+                    "Disabling interface {} after it went over its limits {} times in a row",
+                    name, failures
+                );
+                self.disabled_interfaces
+                    .borrow_mut()
+                    .insert(name.to_owned());
+            }
+        }
+        result
+    }
+
+    /// Create the ScriptHost table for a script, which registers interfaces as belonging to it.
+    fn create_script_host(&self, script: &Path) -> mlua::Result<Table> {
+        let lua = &self.lua;
+        let script_host = lua.create_table()?;
+        script_host.set(
+            "CreateGameInterface",
+            lua.create_function(|lua, (_,): (mlua::Value,)| GameInterface::create_table(lua))?,
+        )?;
+        let directory = fs::canonicalize(script)?
+            .parent()
+            .map(Path::to_owned)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} has no directory", script.display()),
+                )
+            })?;
+        script_host.set(
+            "ReadFile",
+            lua.create_function(move |lua, (_, name): (mlua::Value, String)| {
+                let denied = || {
+                    mlua::Error::external(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("{} is outside of the script's directory", name),
+                    ))
+                };
+                let path = Path::new(&name);
+                if !path
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                {
+                    return Err(denied());
+                }
+                // Check again once links are resolved
+                let path = fs::canonicalize(directory.join(path))?;
+                if !path.starts_with(&directory) {
+                    return Err(denied());
+                }
+                lua.create_string(fs::read(path)?)
+            })?,
+        )?;
+        let interfaces = Rc::clone(&self.game_interfaces);
+        let registrations = Rc::clone(&self.registrations);
+        let script = script.to_owned();
+        script_host.set(
+            "AddGameInterface",
+            lua.create_function(
+                move |_, (_, name, interface): (mlua::Value, String, GameInterface)| {
+                    let order = registrations.get();
+                    registrations.set(order + 1);
+                    interfaces.borrow_mut().insert(
+                        name,
+                        RegisteredInterface {
+                            script: script.clone(),
+                            order,
+                            interface,
+                        },
+                    );
+                    Ok(())
+                },
+            )?,
+        )?;
+        script_host.set(
+            "CommandError",
+            lua.create_function(
+                |_,
+                 (_, reason, argument, description): (
+                    mlua::Value,
+                    ErrorReplyReason,
+                    Option<String>,
+                    Option<String>,
+                )| {
+                    Err::<(), _>(mlua::Error::external(CommandError {
+                        reason,
+                        argument,
+                        description,
+                    }))
+                },
+            )?,
+        )?;
+        Ok(script_host)
+    }
+
+    /// Copy the entries of a table into a new one.
+    fn copy_table(&self, table: &Table) -> mlua::Result<Table> {
+        let copy = self.lua.create_table()?;
+        for pair in table.pairs::<mlua::Value, mlua::Value>() {
+            let (key, value) = pair?;
+            copy.set(key, value)?;
+        }
+        Ok(copy)
+    }
+
+    /// Create a table of the whole standard library for a trusted script. Libraries are copied, so
+    /// that a script changing them doesn't affect other scripts.
+    fn create_library(&self) -> mlua::Result<Table> {
+        let globals = self.lua.globals();
+        let library = self.lua.create_table()?;
+        for pair in globals.pairs::<mlua::Value, mlua::Value>() {
+            match pair? {
+                (key, mlua::Value::Table(table)) if table != globals => {
+                    library.set(key, self.copy_table(&table)?)?
+                }
+                (key, value) => library.set(key, value)?,
+            }
+        }
+        let coroutine = library.get::<Table>("coroutine")?;
+        let (create, wrap) = self.create_coroutine_functions(&coroutine.get("resume")?)?;
+        coroutine.set("create", create)?;
+        coroutine.set("wrap", wrap)?;
+        Ok(library)
+    }
+
+    /// Create a read-only view of a library, so that a script can't change the library itself.
+    fn read_only_library(&self, name: &'static str, library: Table) -> mlua::Result<Table> {
+        let lua = &self.lua;
+        let metatable = lua.create_table()?;
+        metatable.set(
+            "__newindex",
+            lua.create_function(move |_, _: mlua::MultiValue| {
+                Err::<(), _>(mlua::Error::runtime(format!(
+                    "the {} library can't be changed",
+                    name
+                )))
+            })?,
+        )?;
+        let next = lua.globals().get::<Function>("next")?;
+        let entries = library.clone();
+        metatable.set(
+            "__pairs",
+            lua.create_function(move |_, _: mlua::Value| {
+                Ok((next.clone(), entries.clone(), mlua::Value::Nil))
+            })?,
+        )?;
+        metatable.set("__index", library)?;
+        metatable.set("__metatable", false)?;
+        let proxy = lua.create_table()?;
+        proxy.set_metatable(Some(metatable));
+        Ok(proxy)
+    }
+
+    /// Create versions of coroutine.create and coroutine.wrap whose coroutines have the time limit
+    /// applied, resuming them with `resume`.
+    fn create_coroutine_functions(&self, resume: &Function) -> mlua::Result<(Function, Function)> {
+        let lua = &self.lua;
+        let deadline = Rc::clone(&self.deadline);
+        let tripped = Rc::clone(&self.tripped);
+        let limit = self.limits.time;
+        let create = lua.create_function(move |lua, function: Function| {
+            let thread = lua.create_thread(function)?;
+            thread.set_hook(
+                HookTriggers::new().every_nth_instruction(LIMIT_CHECK_INTERVAL),
+                limit_hook(Rc::clone(&deadline), Rc::clone(&tripped), limit),
+            );
+            Ok(thread)
+        })?;
+        let wrap = lua
+            .load(COROUTINE_WRAP)
+            .set_name("=coroutine.wrap")
+            .call((&create, resume))?;
+        Ok((create, wrap))
+    }
+
+    /// Create versions of pcall, xpcall and coroutine.resume that can't catch an error from going
+    /// over the limits.
+    fn create_protected_calls(&self) -> mlua::Result<(Function, Function, Function)> {
+        let lua = &self.lua;
+        let globals = lua.globals();
+        let tripped = Rc::clone(&self.tripped);
+        let exceeded = lua.create_function(move |_, err: mlua::Value| {
+            Ok(tripped.get()
+                || match err {
+                    mlua::Value::String(message) => message == OUT_OF_MEMORY_MESSAGE,
+                    mlua::Value::Error(err) => err.chain().any(|err| {
+                        err.downcast_ref::<LimitExceeded>().is_some()
+                            || matches!(
+                                err.downcast_ref::<mlua::Error>(),
+                                Some(mlua::Error::MemoryError(_))
+                            )
+                    }),
+                    _ => false,
+                })
+        })?;
+        let tripped = Rc::clone(&self.tripped);
+        let limits = self.limits;
+        let raise = lua.create_function(move |_, _: mlua::MultiValue| {
+            Err::<(), _>(mlua::Error::external(if tripped.get() {
+                LimitExceeded::Time(limits.time)
+            } else {
+                LimitExceeded::Memory(limits.memory)
+            }))
+        })?;
+        lua.load(PROTECTED_CALLS)
+            .set_name("=protected calls")
+            .call((
+                globals.get::<Function>("pcall")?,
+                globals.get::<Function>("xpcall")?,
+                globals
+                    .get::<Table>("coroutine")?
+                    .get::<Function>("resume")?,
+                exceeded,
+                raise,
+            ))
+    }
+
+    /// Create a table of the parts of the standard library that can't reach outside of the bridge.
+    /// Libraries are read-only, so that a script can't change them for other scripts, and errors
+    /// from going over the limits can't be caught.
+    fn create_sandbox(&self) -> mlua::Result<Table> {
+        let globals = self.lua.globals();
+        let sandbox = self.lua.create_table()?;
+        for name in SANDBOX_GLOBALS {
+            sandbox.set(*name, globals.get::<mlua::Value>(*name)?)?;
+        }
+        // Finalizers run whenever the garbage collector gets to them, outside of the limits
+        let setmetatable = globals.get::<Function>("setmetatable")?;
+        sandbox.set(
+            "setmetatable",
+            self.lua.create_function(
+                move |_, (table, metatable): (mlua::Value, mlua::Value)| {
+                    if let mlua::Value::Table(metatable) = &metatable {
+                        if !metatable.raw_get::<mlua::Value>("__gc")?.is_nil() {
+                            return Err(mlua::Error::runtime(
+                                "sandboxed scripts can't set __gc metamethods",
+                            ));
+                        }
+                    }
+                    setmetatable.call::<mlua::Value>((table, metatable))
+                },
+            )?,
+        )?;
+        let (pcall, xpcall, resume) = self.create_protected_calls()?;
+        sandbox.set("pcall", pcall)?;
+        sandbox.set("xpcall", xpcall)?;
+        for name in SANDBOX_LIBRARIES {
+            let mut library = globals.get::<Table>(*name)?;
+            if *name == "coroutine" {
+                let (create, wrap) = self.create_coroutine_functions(&resume)?;
+                library = self.copy_table(&library)?;
+                library.set("create", create)?;
+                library.set("resume", resume.clone())?;
+                library.set("wrap", wrap)?;
+            }
+            sandbox.set(*name, self.read_only_library(name, library)?)?;
+        }
+        let os = globals.get::<Table>("os")?;
+        let sandbox_os = self.lua.create_table()?;
+        for name in SANDBOX_OS_FUNCTIONS {
+            sandbox_os.set(*name, os.get::<mlua::Value>(*name)?)?;
+        }
+        sandbox.set("os", self.read_only_library("os", sandbox_os)?)?;
+        Ok(sandbox)
+    }
+
+    /// Run a script in an environment of its own, so that its globals can't clash with those of
+    /// other scripts. Interfaces it registered when it last ran are replaced. Sandboxed scripts
+    /// only get the parts of the standard library that can't touch the rest of the system, and
+    /// can't be precompiled bytecode.
+    pub fn run_script(&self, path: impl AsRef<Path>, sandboxed: bool) -> mlua::Result<()> {
+        let path = path.as_ref();
         let data = fs::read(path)?;
-        let script = self.lua.load(data);
-        script.exec()?;
-        Ok(())
+        self.unload_script(path);
+
+        let environment = self.lua.create_table()?;
+        environment.set("ScriptHost", self.create_script_host(path)?)?;
+        environment.set("GameCube", self.create_gamecube()?)?;
+        environment.set("_G", &environment)?;
+        let library = if sandboxed {
+            self.create_sandbox()?
+        } else {
+            self.create_library()?
+        };
+        let metatable = self.lua.create_table()?;
+        metatable.set("__index", library)?;
+        environment.set_metatable(Some(metatable));
+
+        let chunk = self
+            .lua
+            .load(data)
+            .set_name(format!("@{}", path.display()))
+            .set_environment(environment);
+        if sandboxed {
+            self.limited(|| chunk.set_mode(ChunkMode::Text).exec())
+        } else {
+            self.limited(|| chunk.exec())
+        }
+    }
+
+    /// Remove the interfaces a script registered, disconnecting if one of them is in use.
+    fn unload_script(&self, path: &Path) {
+        let active_name = self.interface_name();
+        let mut unloaded_active = false;
+        self.game_interfaces
+            .borrow_mut()
+            .retain(|name, registered| {
+                let keep = registered.script != path;
+                unloaded_active |= !keep && active_name.as_deref() == Some(name);
+                keep
+            });
+        if unloaded_active {
+            self.disconnect();
+        }
     }
 
     pub fn is_connected(&self) -> bool {
@@ -442,38 +1965,104 @@ impl LuaInterface {
             .is_some()
     }
 
+    /// Connect to the game using the interface that matches it. When several match, the one with
+    /// the highest priority is used, then the one registered first. If an interface name is
+    /// forced, only that interface is tried.
     pub fn connect(
         &self,
         connection: Box<dyn GameCubeConnection>,
+        forced_interface: Option<&str>,
     ) -> Result<(String, GameInterface), Box<dyn GameCubeConnection>> {
         self.disconnect();
 
-        self.connection
-            .borrow_mut()
-            .replace(LuaGcnConnection::connect(connection, None));
+        // Read the disc header once, rather than in every interface's VerifyFunc. If it can't be
+        // read, no interface is tried, since the header wouldn't match when verifying the game
+        let mut gcn_connection = LuaGcnConnection::connect(connection, None);
+        gcn_connection.disc_header =
+            match DiscHeader::read(gcn_connection.gamecube_connection.as_ref()) {
+                Ok(disc_header) => disc_header,
+                Err(err) => {
+                    eprintln!("Couldn't read the disc header: {}", err);
+                    return Err(gcn_connection.gamecube_connection);
+                }
+            };
+        let disc_header = gcn_connection.disc_header.clone();
+        self.connection.borrow_mut().replace(gcn_connection);
 
-        let interfaces = self.game_interfaces.borrow();
-        let interface = interfaces
+        // The interfaces are copied out, since a VerifyFunc may register more of them
+        let interfaces = self
+            .game_interfaces
+            .borrow()
             .iter()
-            .filter_map(|(name, interface)| match interface.verify() {
-                Ok(true) => Some((name, interface)),
-                Ok(false) => None,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    None
+            .map(|(name, registered)| {
+                (name.clone(), registered.order, registered.interface.clone())
+            })
+            .collect::<Vec<_>>();
+        let disabled_interfaces = self.disabled_interfaces.borrow().clone();
+        let mut candidates = interfaces
+            .into_iter()
+            .filter(|(name, _, _)| !disabled_interfaces.contains(name))
+            .filter(|(name, _, _)| forced_interface.is_none_or(|forced| forced == name))
+            .map(|(name, order, interface)| {
+                let priority = interface.priority().unwrap_or_else(|err| {
+                    eprintln!("{}: {}", name, err);
+                    0
+                });
+                (priority, order, name, interface)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(priority, order, _, _)| (Reverse(*priority), *order));
+
+        // Verify every candidate, so that games matched by more than one interface are noticed
+        let matches = candidates
+            .into_iter()
+            .filter(|(_, _, name, interface)| {
+                match self.limited_interface(Some(name), || interface.verify(disc_header.as_ref()))
+                {
+                    Ok(verified) => verified,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        false
+                    }
                 }
             })
+            .collect::<Vec<_>>();
+        if let Some((priority, _, name, _)) = matches.first() {
+            let tied = matches
+                .iter()
+                .take_while(|(other_priority, _, _, _)| other_priority == priority)
+                .map(|(_, _, other_name, _)| other_name.as_str())
+                .collect::<Vec<_>>();
+            if tied.len() > 1 {
+                eprintln!(
+                    "Warning: interfaces {} all match this game with priority {}; using {}",
+                    tied.join(", "),
+                    priority,
+                    name
+                );
+            }
+        }
+        let interface = matches
+            .into_iter()
             .next()
-            .map(|(k, v)| (k.clone(), v.clone()));
+            .map(|(_, _, name, interface)| (name, interface));
 
         let mut connection = self.connection.borrow_mut();
         match interface {
             Some((name, interface)) => {
-                connection
+                let connection = connection
                     .as_mut()
-                    .expect("GCN connection was unexpectedly set None")
-                    .game_interface
-                    .replace(interface.clone());
+                    .expect("GCN connection was unexpectedly set None");
+                connection.game_interface.replace(interface.clone());
+                connection.interface_name = Some(name.clone());
+                connection.schema = Rc::new(interface.schema().unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    Default::default()
+                }));
+                connection.normalization = interface.normalization().unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    Default::default()
+                });
                 Ok((name, interface))
             }
             None => {
@@ -486,18 +2075,142 @@ impl LuaInterface {
         }
     }
 
+    /// Get the name the active interface was registered under.
+    pub fn interface_name(&self) -> Option<String> {
+        self.connection
+            .borrow()
+            .as_ref()
+            .and_then(|c| c.interface_name.clone())
+    }
+
+    /// Disconnect, handing back the connection to the console so it can be used again.
+    pub fn take_connection(&self) -> Option<Box<dyn GameCubeConnection>> {
+        self.connection
+            .borrow_mut()
+            .take()
+            .map(|c| c.gamecube_connection)
+    }
+
     pub fn disconnect(&self) {
         self.connection.borrow_mut().take();
     }
 
+    /// Get the active interface's variable policies.
+    pub fn variable_policies(&self) -> mlua::Result<HashMap<String, VariablePolicy>> {
+        let connection = self.connection.borrow();
+        match connection.as_ref().and_then(|c| c.game_interface.as_ref()) {
+            Some(interface) => interface.variable_policies(),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    /// Get the active interface's derived variables.
+    pub fn derived_variables(&self) -> mlua::Result<Vec<DerivedVariable>> {
+        let connection = self.connection.borrow();
+        match connection.as_ref().and_then(|c| c.game_interface.as_ref()) {
+            Some(interface) => interface.derived_variables(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Get the active interface's rules for normalizing numbers.
+    fn normalization(&self) -> Normalization {
+        self.connection
+            .borrow()
+            .as_ref()
+            .map(|c| c.normalization)
+            .unwrap_or_default()
+    }
+
+    /// Compute a derived variable, given a JSON object of its dependencies' values. The result is
+    /// checked against the schema like a written value, and is None if the schema rejects it.
+    pub fn compute_derived(
+        &self,
+        derived: &DerivedVariable,
+        values: &JsonValue,
+    ) -> mlua::Result<Option<JsonValue>> {
+        let values = convert_json_to_lua(&self.lua, values)?;
+        let result: mlua::Value = self
+            .limited_interface(self.interface_name().as_deref(), || {
+                derived.function.call(values)
+            })?;
+        let value = convert_lua_to_json(&self.lua, &result, &self.normalization())?;
+        let schema = self
+            .connection
+            .borrow()
+            .as_ref()
+            .map(|c| Rc::clone(&c.schema));
+        let Some(schema) = schema else {
+            return Ok(Some(value));
+        };
+        let (value, violations) = schema.validate(&derived.name, value);
+        for violation in violations {
+            eprintln!("{}", violation);
+        }
+        Ok(value)
+    }
+
+    /// The game ID from the disc header of the connected game.
+    pub fn game_id(&self) -> Option<String> {
+        let connection = self.connection.borrow();
+        let header = connection.as_ref()?.disc_header.as_ref()?;
+        Some(header.game_id.clone())
+    }
+
+    /// Get the active interface's PersistentData table as JSON.
+    pub fn persistent_data(&self) -> Option<mlua::Result<JsonValue>> {
+        let connection = self.connection.borrow();
+        let interface = connection.as_ref()?.game_interface.as_ref()?;
+        Some(
+            interface
+                .0
+                .get::<mlua::Value>("PersistentData")
+                .and_then(|data| convert_lua_to_json(&self.lua, &data, &Normalization::LOSSLESS)),
+        )
+    }
+
+    /// Replace the active interface's PersistentData table with data from a previous run.
+    pub fn restore_persistent_data(&self, data: &JsonValue) -> mlua::Result<()> {
+        let connection = self.connection.borrow();
+        let interface = connection
+            .as_ref()
+            .and_then(|c| c.game_interface.as_ref())
+            .ok_or(io::Error::from(io::ErrorKind::NotConnected))?;
+        let data = match convert_json_to_lua(&self.lua, data)? {
+            mlua::Value::Table(table) => table,
+            _ => self.lua.create_table()?,
+        };
+        interface.0.set("PersistentData", data)
+    }
+
     pub fn verify_current_game(&self) -> Result<(), VerificationError> {
         let connection = self.connection.borrow();
+        let connection = connection.as_ref().ok_or(VerificationError::NotConnected)?;
         let interface = connection
+            .game_interface
             .as_ref()
-            .and_then(|connection| connection.game_interface.as_ref())
             .ok_or(VerificationError::NotConnected)?;
+        let name = connection.interface_name.as_deref();
+        if name.is_some_and(|name| self.disabled_interfaces.borrow().contains(name)) {
+            return Err(VerificationError::VerificationFailed);
+        }
+
+        // A different disc means a different game, without needing to ask the interface
+        let disc_header = DiscHeader::read(connection.gamecube_connection.as_ref())
+            .map_err(|err| VerificationError::VerificationError(mlua::Error::external(err)))?;
+        if disc_header != connection.disc_header {
+            return Err(VerificationError::VerificationFailed);
+        }
 
-        match interface.verify() {
+        // The header is the one the interface was matched against, so an interface that declares
+        // its GameIDs doesn't need its VerifyFunc called again
+        let verified = self.limited_interface(name, || {
+            match interface.matches_disc(disc_header.as_ref())? {
+                Some(matches) => Ok(matches),
+                None => interface.verify(disc_header.as_ref()),
+            }
+        });
+        match verified {
             Ok(true) => Ok(()),
             Ok(false) => Err(VerificationError::VerificationFailed),
             Err(err) => Err(VerificationError::VerificationError(err)),
@@ -510,27 +2223,164 @@ impl LuaInterface {
             .as_ref()
             .and_then(|c| c.game_interface.as_ref())
             .map(|interface| {
-                InfoCommand::new(
+                let features = interface.features().unwrap_or(None);
+                let features = features
+                    .as_ref()
+                    .map(|f| f.iter().map(String::as_str).collect::<Vec<_>>());
+                let slots = interface.slots().unwrap_or(None);
+                let slots = slots
+                    .as_ref()
+                    .map(|s| s.iter().map(String::as_str).collect::<Vec<_>>());
+                InfoCommand::with_features(
                     interface.name().unwrap_or(None).as_deref(),
                     interface.version().unwrap_or(None).as_deref(),
+                    features.as_deref(),
+                    slots.as_deref(),
                 )
             })
     }
 
-    pub fn run_game_watcher(&self) -> Option<mlua::Result<Vec<(String, mlua::Result<JsonValue>)>>> {
+    /// Run the active interface's handler for a custom command. Variables written by the handler
+    /// are returned to be applied like the game watcher's, along with a Reply with its return
+    /// value if it wasn't nil. If any of the values can't be written, none of them are.
+    pub fn run_command(&self, command: &CustomCommand) -> Result<CommandUpdate, ErrorReplyCommand> {
+        let name = command.name();
+        let script_error = |err: mlua::Error| {
+            if let Some(err) = err.chain().find_map(|e| e.downcast_ref::<CommandError>()) {
+                return ErrorReplyCommand::with_argument_and_description(
+                    name,
+                    err.argument.as_deref(),
+                    err.reason,
+                    err.description.as_deref(),
+                );
+            }
+            eprintln!("{}", err);
+            ErrorReplyCommand::with_description(
+                name,
+                ErrorReplyReason::Unknown,
+                Some(&err.to_string()),
+            )
+        };
+
         let connection = self.connection.borrow();
         let interface = connection
             .as_ref()
-            .and_then(|c| c.game_interface.as_ref())?;
+            .and_then(|c| c.game_interface.as_ref())
+            .ok_or_else(|| ErrorReplyCommand::new(name, ErrorReplyReason::UnknownCmd))?;
+        let handler = interface
+            .command_handler(name)
+            .map_err(script_error)?
+            .ok_or_else(|| ErrorReplyCommand::new(name, ErrorReplyReason::UnknownCmd))?;
+
+        let arguments = convert_json_to_lua(&self.lua, command.arguments()).map_err(|err| {
+            ErrorReplyCommand::with_description(
+                name,
+                ErrorReplyReason::BadValue,
+                Some(&err.to_string()),
+            )
+        })?;
+        let schema = connection
+            .as_ref()
+            .map(|c| Rc::clone(&c.schema))
+            .unwrap_or_default();
+        let normalization = connection
+            .as_ref()
+            .map(|c| c.normalization)
+            .unwrap_or_default();
+        let (store, table) =
+            VariableStore::new(&self.lua, schema, normalization).map_err(script_error)?;
+        let interface_name = connection
+            .as_ref()
+            .and_then(|c| c.interface_name.as_deref());
+        let result = self
+            .limited_interface(interface_name, || {
+                interface.run_command(&handler, arguments, &table)
+            })
+            .map_err(script_error)?;
+
+        let mut operations = Vec::new();
+        for operation in store.unwrap() {
+            if let VariableOperation::Write(_, Err(err)) = operation {
+                return Err(script_error(err));
+            }
+            operations.push(operation);
+        }
+        let reply = match result {
+            mlua::Value::Nil => None,
+            result => {
+                let value = convert_lua_to_json(&self.lua, &result, &normalization)
+                    .map_err(script_error)?;
+                Some(ServerCommand::reply(name, value))
+            }
+        };
+        Ok(CommandUpdate { operations, reply })
+    }
+
+    /// Perform reads requested by a ReadMemory command, returning the values read as JSON.
+    pub fn read_memory(&self, command: &ReadMemoryCommand) -> Result<JsonValue, ErrorReplyCommand> {
+        let error = |reason, err: &dyn Display| {
+            ErrorReplyCommand::with_description("ReadMemory", reason, Some(&err.to_string()))
+        };
+
+        if command.reads().is_null() {
+            return Err(ErrorReplyCommand::with_argument_and_description(
+                "ReadMemory",
+                Some("reads"),
+                ErrorReplyReason::MissingArgument,
+                None,
+            ));
+        }
+
+        let connection = self.connection.borrow();
+        let connection = connection
+            .as_ref()
+            .ok_or_else(|| error(ErrorReplyReason::Unknown, &"not connected"))?;
+        let read_list = convert_json_to_lua(&self.lua, command.reads())
+            .and_then(|reads| Vec::<Table>::from_lua(reads, &self.lua))
+            .and_then(|reads| read_list_from_tables(reads, &self.lua))
+            .map_err(|err| error(ErrorReplyReason::BadValue, &err))?;
+        let values = read_batch(
+            &self.lua,
+            connection.gamecube_connection.as_ref(),
+            read_list,
+        )
+        .map_err(|err| error(ErrorReplyReason::Unknown, &err))?;
+        values
+            .iter()
+            .map(|value| convert_lua_to_json(&self.lua, value, &connection.normalization))
+            .collect::<mlua::Result<Vec<_>>>()
+            .map(JsonValue::Array)
+            .map_err(|err| error(ErrorReplyReason::Unknown, &err))
+    }
+
+    pub fn run_game_watcher(&self) -> Option<mlua::Result<GameWatcherUpdate>> {
+        let connection = self.connection.borrow();
+        let connection = connection.as_ref()?;
+        let interface = connection.game_interface.as_ref()?;
         Some(
-            VariableStore::new(&self.lua)
-                .and_then(|(store, table)| interface.run_game_watcher(&table).map(|_| store))
-                .map(VariableStore::unwrap),
+            VariableStore::new(
+                &self.lua,
+                Rc::clone(&connection.schema),
+                connection.normalization,
+            )
+            .and_then(|(store, table)| {
+                let name = connection.interface_name.as_deref();
+                self.limited_interface(name, || interface.run_game_watcher(&table))?;
+                if let Some(name) = name {
+                    self.limit_failures.borrow_mut().remove(name);
+                }
+                Ok(store)
+            })
+            .map(|store| GameWatcherUpdate {
+                loading: store.is_loading(),
+                operations: store.unwrap(),
+            }),
         )
     }
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum VerificationError {
     NotConnected,
     VerificationFailed,
@@ -553,3 +2403,183 @@ impl Display for VerificationError {
 }
 
 impl Error for VerificationError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(name: &str, bytes: &[u8]) -> mlua::Value {
+        let ty = TypeSpecifier::from_name(name).unwrap();
+        convert_bytes(&Lua::new(), Some(bytes.to_vec()), &ty).unwrap()
+    }
+
+    #[test]
+    fn type_specifier_names() {
+        assert!(matches!(
+            TypeSpecifier::from_name("i16"),
+            Some(TypeSpecifier::S16)
+        ));
+        assert!(matches!(
+            TypeSpecifier::from_name("u32be"),
+            Some(TypeSpecifier::U32)
+        ));
+        assert!(matches!(
+            TypeSpecifier::from_name("f32le"),
+            Some(TypeSpecifier::LittleEndian(ty)) if matches!(*ty, TypeSpecifier::F32)
+        ));
+        assert!(matches!(
+            TypeSpecifier::from_name("fx16.16"),
+            Some(TypeSpecifier::Fixed {
+                integer_bits: 16,
+                fraction_bits: 16
+            })
+        ));
+        assert_eq!(TypeSpecifier::from_name("fx8.8le").unwrap().size(), 2);
+        for name in ["u8le", "boolbe", "u32lele", "fx4.2", "fx200.200", "u24", ""] {
+            assert!(TypeSpecifier::from_name(name).is_none(), "{name}");
+        }
+    }
+
+    #[test]
+    fn convert_integers() {
+        assert_eq!(convert("s8", &[0xFF]), mlua::Value::Integer(-1));
+        assert_eq!(convert("u16", &[0x12, 0x34]), mlua::Value::Integer(0x1234));
+        assert_eq!(
+            convert("u16le", &[0x12, 0x34]),
+            mlua::Value::Integer(0x3412)
+        );
+        assert_eq!(convert("s32", &[0xFF; 4]), mlua::Value::Integer(-1));
+        assert_eq!(convert("u64", &[0xFF; 8]), mlua::Value::Integer(-1));
+        assert_eq!(convert("bool", &[0x02]), mlua::Value::Boolean(true));
+        assert_eq!(convert("bool", &[0x00]), mlua::Value::Boolean(false));
+    }
+
+    #[test]
+    fn convert_floats() {
+        assert_eq!(
+            convert("f32", &[0x3F, 0xC0, 0, 0]),
+            mlua::Value::Number(1.5)
+        );
+        assert_eq!(
+            convert("f32le", &[0, 0, 0xC0, 0x3F]),
+            mlua::Value::Number(1.5)
+        );
+        assert_eq!(convert("fx8.8", &[0x01, 0x80]), mlua::Value::Number(1.5));
+        assert_eq!(convert("fx8.8", &[0xFF, 0x80]), mlua::Value::Number(-0.5));
+        assert_eq!(convert("fx8.8le", &[0x80, 0x01]), mlua::Value::Number(1.5));
+    }
+
+    #[test]
+    fn convert_bit_fields() {
+        let lua = Lua::new();
+        let bits = |base: TypeSpecifier, shift, width, bytes: &[u8]| {
+            let ty = TypeSpecifier::Bits {
+                base: Box::new(base),
+                shift,
+                width,
+            };
+            convert_bytes(&lua, Some(bytes.to_vec()), &ty).unwrap()
+        };
+        let u16le = || TypeSpecifier::LittleEndian(Box::new(TypeSpecifier::U16));
+        assert_eq!(
+            bits(TypeSpecifier::U16, 4, 8, &[0x12, 0x34]),
+            mlua::Value::Integer(0x23)
+        );
+        assert_eq!(
+            bits(u16le(), 4, 8, &[0x34, 0x12]),
+            mlua::Value::Integer(0x23)
+        );
+        assert_eq!(
+            bits(TypeSpecifier::U64, 0, 64, &[0xFF; 8]),
+            mlua::Value::Integer(-1)
+        );
+    }
+
+    #[test]
+    fn missing_reads_are_nil() {
+        let value = convert_bytes(&Lua::new(), None, &TypeSpecifier::U32).unwrap();
+        assert_eq!(value, mlua::Value::Nil);
+    }
+
+    #[test]
+    fn decode_strings_up_to_the_terminator() {
+        assert_eq!(StringEncoding::Utf8.decode(b"Samus\0junk"), "Samus");
+        assert_eq!(StringEncoding::Utf8.decode(b"full"), "full");
+        assert_eq!(StringEncoding::Utf8.decode(b"a\xFFb"), "a\u{FFFD}b");
+        assert_eq!(
+            StringEncoding::ShiftJis.decode(b"\x83\x8A\x83\x93\x83\x4E\0"),
+            "リンク"
+        );
+        assert_eq!(
+            StringEncoding::Utf16Be.decode(b"\0A\x01\0\0\0\0B"),
+            "A\u{100}"
+        );
+        assert_eq!(
+            StringEncoding::Utf16Le.decode(b"A\0\0\x01\0\0B\0"),
+            "A\u{100}"
+        );
+    }
+
+    /// A console with no disc in it.
+    struct EmptyConnection;
+
+    impl GameCubeConnection for EmptyConnection {
+        fn read(&self, read_list: &[Read]) -> io::Result<Vec<Option<Vec<u8>>>> {
+            Ok(vec![None; read_list.len()])
+        }
+    }
+
+    fn lua_interface() -> LuaInterface {
+        LuaInterface::new(ScriptLimits {
+            time: Duration::from_secs(1),
+            memory: 16 * 1024 * 1024,
+        })
+        .unwrap()
+    }
+
+    /// Run a sandboxed script from a temporary file.
+    fn run_script(lua_interface: &LuaInterface, name: &str, source: &str) -> mlua::Result<()> {
+        let path = std::env::temp_dir().join(format!("{}-{}.lua", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        let result = lua_interface.run_script(&path, true);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn sandboxed_scripts_cant_set_finalizers() {
+        let lua_interface = lua_interface();
+        let result = run_script(
+            &lua_interface,
+            "finalizer",
+            "setmetatable({}, {__index = {}})\n\
+             setmetatable({}, {__gc = function() while true do end end})",
+        );
+        assert!(result.unwrap_err().to_string().contains("__gc"));
+        lua_interface.lua.gc_collect().unwrap();
+    }
+
+    #[test]
+    fn verify_func_can_register_interfaces() {
+        let lua_interface = lua_interface();
+        run_script(
+            &lua_interface,
+            "register",
+            r#"
+            local interface = ScriptHost:CreateGameInterface()
+            interface.VerifyFunc = function(self)
+                ScriptHost:AddGameInterface("extra", ScriptHost:CreateGameInterface())
+                return true
+            end
+            ScriptHost:AddGameInterface("verifier", interface)
+            "#,
+        )
+        .unwrap();
+        let connected = lua_interface.connect(Box::new(EmptyConnection), Some("verifier"));
+        assert_eq!(
+            connected.ok().map(|(name, _)| name).as_deref(),
+            Some("verifier")
+        );
+        assert!(lua_interface.game_interfaces.borrow().contains_key("extra"));
+    }
+}
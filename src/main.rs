@@ -1,24 +1,45 @@
+mod cli;
 mod connection;
+mod derived;
+mod event_log;
 mod lua;
+mod mapping;
+mod normalization;
+mod policy;
+mod schema;
+mod scripts;
+mod snapshot;
 mod uat;
 
 use std::{
+    collections::BTreeSet,
     env::{self, VarError},
     error::Error,
+    fs,
     io::ErrorKind,
-    net::{IpAddr, Ipv4Addr},
-    path::PathBuf,
+    mem,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process,
     str::FromStr,
     sync::mpsc::{channel, TryRecvError},
     thread::{self},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use cli::Options;
 use connection::GameCubeConnection;
-use lua::{LuaInterface, VerificationError};
+use derived::{compute_all_derived_variables, sort_derived_variables, update_derived_variables};
+use event_log::EventLog;
+use json::JsonValue;
+use lua::{LuaInterface, ScriptLimits, VariableOperation, VerificationError};
+use mapping::VariableMap;
+use policy::VariableFilter;
+use scripts::{find_scripts, ScriptWatcher};
+use snapshot::Snapshot;
 use uat::{
-    command::{ClientCommand, ServerCommand},
-    variable::VariableStore,
+    command::{ClientCommand, ErrorReplyReason, ServerCommand},
+    variable::{is_in_subtree, normalize_path, split_slot, VariableStore},
     Client, Server,
 };
 
@@ -28,6 +49,8 @@ use crate::connection::nintendont::NintendontConnection;
 
 const CONNECTION_ATTEMPT_INTERVAL: Duration = Duration::from_secs(5);
 const GAME_WATCH_INTERVAL: Duration = Duration::from_millis(500);
+const CLIENT_PING_INTERVAL: Duration = Duration::from_secs(10);
+const CLIENT_PONG_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[cfg(target_os = "windows")]
 fn connect_to_dolphin() -> Box<dyn GameCubeConnection> {
@@ -75,11 +98,60 @@ fn connect_to_nintendont(address: IpAddr) -> Box<dyn GameCubeConnection> {
     result
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut argv = env::args();
-    argv.next(); // Consume argv[0]
+/// The Var command for a variable, sent for a slot if its path starts with the slot's name.
+fn var_command(name: &str, value: JsonValue, slots: &[String]) -> ServerCommand {
+    let (slot, name) = split_slot(name, slots);
+    ServerCommand::var_with_slot(name, value, slot)
+}
+
+fn client_name(client: &Client) -> String {
+    match client.address() {
+        Some(address) => format!("Client {}", address),
+        None => "Client".into(),
+    }
+}
+
+fn is_trusted(script: &Path, trusted_paths: &[PathBuf]) -> bool {
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let script = canonical(script);
+    trusted_paths
+        .iter()
+        .any(|trusted| script.starts_with(canonical(trusted)))
+}
 
-    let target = argv.next().ok_or("Need IP Address or to specify Dolphin")?;
+/// Run the scripts in the search paths in a new Lua state, reporting any errors. Scripts are
+/// sandboxed unless they're beneath one of the trusted paths. Returns the scripts that failed to
+/// run along with the state.
+fn load_scripts(
+    search_paths: &[PathBuf],
+    trusted_paths: &[PathBuf],
+    limits: ScriptLimits,
+) -> mlua::Result<(LuaInterface, BTreeSet<PathBuf>)> {
+    let lua_interface = LuaInterface::new(limits)?;
+    let (scripts, errors) = find_scripts(search_paths);
+    for err in errors {
+        eprintln!("{err}");
+    }
+    let mut failed_scripts = BTreeSet::new();
+    for path in scripts {
+        let sandboxed = !is_trusted(&path, trusted_paths);
+        if let Err(err) = lua_interface.run_script(&path, sandboxed) {
+            eprintln!("{}: {err}", path.to_string_lossy());
+            failed_scripts.insert(path);
+        }
+    }
+    Ok((lua_interface, failed_scripts))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+    let target = options.target;
 
     let connection_factory: Box<dyn Fn() -> Box<dyn GameCubeConnection>> =
         if target.to_lowercase() == "dolphin" {
@@ -93,7 +165,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             Box::new(move || connect_to_nintendont(address))
         };
 
-    let mut search_paths = argv.map(PathBuf::from).collect::<Vec<_>>();
+    let mut search_paths = options.search_paths;
     let key = "GAMECUBE_UAT_BRIDGE_PATH";
     match env::var(key) {
         Ok(value) => {
@@ -107,73 +179,130 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    let lua_interface = LuaInterface::new()?;
-    for search_path in search_paths {
-        if search_path.is_file() {
-            if let Err(err) = lua_interface.run_script(&search_path) {
-                let path = search_path.to_string_lossy();
-                eprintln!("{path}: {err}")
+    let variable_map = match &options.variable_map {
+        Some(path) => match VariableMap::load(path) {
+            Ok(variable_map) => variable_map,
+            Err(err) => {
+                eprintln!("Couldn't load variable map: {}", err);
+                process::exit(1);
             }
-        } else if search_path.is_dir() {
-            let dir_entries = match search_path.read_dir() {
-                Ok(d) => d,
+        },
+        None => VariableMap::default(),
+    };
+
+    let mut script_watcher = ScriptWatcher::new(search_paths.clone());
+    let (mut lua_interface, mut failed_scripts) = load_scripts(
+        &search_paths,
+        &options.trusted_scripts,
+        options.script_limits,
+    )?;
+
+    let uat_server = Server::new(
+        options.bind_address,
+        &options.ports,
+        options.allowed_origins,
+    )?;
+    let (client_sender, client_receiver) = channel();
+    println!("Listening for UAT clients on {}", uat_server.local_addr()?);
+    thread::spawn(move || {
+        for client in uat_server.accept_clients() {
+            let client = match client {
+                Ok(client) => client,
                 Err(err) => {
-                    eprintln!("{err}");
+                    eprintln!("{}", err);
                     continue;
                 }
             };
-            for entry in dir_entries {
-                let file = match entry {
-                    Ok(f) => f,
-                    Err(err) => {
-                        eprintln!("{err}");
-                        break;
-                    }
-                };
-                match file.file_type() {
-                    Ok(t) => {
-                        if !t.is_file() {
-                            continue;
-                        }
-                    }
-                    Err(_) => continue,
-                }
-                if !file.file_name().to_string_lossy().ends_with(".lua") {
-                    continue;
-                }
-                let path = file.path();
-                if let Err(err) = lua_interface.run_script(&path) {
-                    let path = path.to_string_lossy();
-                    eprintln!("{path}: {err}")
-                }
-            }
-        }
-    }
-
-    let uat_server = Server::new(Ipv4Addr::LOCALHOST)?;
-    let (client_sender, client_receiver) = channel();
-    println!(
-        "Listening for UAT clients on port {}",
-        uat_server.local_addr()?.port()
-    );
-    thread::spawn(move || {
-        for client in uat_server.accept_clients().filter_map(Result::ok) {
-            if let Err(_) = client_sender.send(client) {
+            if client_sender.send(client).is_err() {
                 break;
             };
         }
         println!("Server closed");
     });
 
+    // Variables as written by the game watcher, and as sent to clients after applying policies
+    let mut raw_variable_store = VariableStore::new();
     let mut variable_store = VariableStore::new();
+    let mut variable_filter = VariableFilter::default();
+    let mut derived_variables = Vec::new();
+    // Set when every derived variable should be computed again, such as after connecting
+    let mut recompute_derived = false;
+    let mut slots = Vec::new();
+    let mut snapshot = options
+        .snapshot
+        .as_ref()
+        .and_then(|path| match Snapshot::load(path) {
+            Ok(snapshot) => Some(snapshot),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => {
+                eprintln!("Couldn't load snapshot: {}", err);
+                None
+            }
+        });
+    if let Some(snapshot) = &snapshot {
+        println!("Restored snapshot of {}", snapshot.game_id);
+        for (name, value) in &snapshot.variables {
+            raw_variable_store.update_variable(name, value.clone());
+            variable_store.update_variable(name, value.clone());
+        }
+    }
+    // Game ID and interface name of the game being snapshotted
+    let mut snapshot_game: Option<(String, String)> = None;
+    let mut saved_persistent_data = None;
+
+    let mut event_log = match &options.event_log {
+        Some(path) => Some(EventLog::open(path, options.event_log_max_size)?),
+        None => None,
+    };
+
+    // The connection to the console and the interface's persistent data, kept across a reload
+    let mut reused_connection = None;
+    let mut reloaded_persistent_data = None;
+    let mut command_operations = Vec::new();
+
     let mut clients: Vec<Client> = Vec::new();
     loop {
+        if script_watcher.changed() {
+            println!("Scripts changed, reloading");
+            match load_scripts(
+                &search_paths,
+                &options.trusted_scripts,
+                options.script_limits,
+            ) {
+                // Scripts that were already broken don't stop the others from being reloaded
+                Ok((new_interface, failed)) if failed.is_subset(&failed_scripts) => {
+                    reloaded_persistent_data = lua_interface
+                        .interface_name()
+                        .zip(lua_interface.persistent_data().and_then(Result::ok));
+                    reused_connection = lua_interface.take_connection();
+                    if reused_connection.is_some() {
+                        if let Some(event_log) = &mut event_log {
+                            event_log.disconnected("scripts reloaded");
+                        }
+                    }
+                    lua_interface = new_interface;
+                    failed_scripts = failed;
+                }
+                Ok(_) => println!("Keeping the previous scripts until the errors are fixed"),
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+
+        let mut removed_variables = Vec::new();
         match lua_interface.verify_current_game() {
-            Ok(_) => {}
+            Ok(_) => {
+                if let Some(event_log) = &mut event_log {
+                    event_log.verified();
+                }
+            }
             Err(VerificationError::NotConnected) => {}
             Err(VerificationError::VerificationFailed) => {
                 println!("Current interface failed to re-verify, disconnecting.");
                 lua_interface.disconnect();
+                if let Some(event_log) = &mut event_log {
+                    event_log.verification_failed("interface rejected game");
+                    event_log.disconnected("verification failed");
+                }
             }
             Err(VerificationError::VerificationError(err)) => {
                 println!("{}", err);
@@ -181,12 +310,18 @@ fn main() -> Result<(), Box<dyn Error>> {
                     "Current interface encountered an error while re-verifying, disconnecting."
                 );
                 lua_interface.disconnect();
+                if let Some(event_log) = &mut event_log {
+                    event_log.verification_failed(&err.to_string());
+                    event_log.disconnected("verification failed");
+                }
             }
         }
 
         if !lua_interface.is_connected() {
-            let connection = connection_factory();
-            match lua_interface.connect(connection) {
+            let connection = reused_connection
+                .take()
+                .unwrap_or_else(|| connection_factory());
+            match lua_interface.connect(connection, options.interface.as_deref()) {
                 Ok((name, interface)) => {
                     println!(
                         "Found interface {} for {}",
@@ -196,6 +331,69 @@ fn main() -> Result<(), Box<dyn Error>> {
                             .unwrap_or_else(|_| Some("<invalid>".into()))
                             .unwrap_or_else(|| "<nil>".into())
                     );
+
+                    variable_filter = VariableFilter::new(
+                        lua_interface.variable_policies().unwrap_or_else(|err| {
+                            eprintln!("{}", err);
+                            Default::default()
+                        }),
+                    );
+
+                    slots = interface.slots().ok().flatten().unwrap_or_default();
+
+                    derived_variables = sort_derived_variables(
+                        lua_interface.derived_variables().unwrap_or_else(|err| {
+                            eprintln!("{}", err);
+                            Vec::new()
+                        }),
+                    );
+                    recompute_derived = true;
+
+                    let game_id = lua_interface.game_id();
+                    if let Some(event_log) = &mut event_log {
+                        event_log.connected(&name, game_id.as_deref());
+                    }
+
+                    if options.snapshot.is_some() {
+                        let game_id = game_id.unwrap_or_default();
+                        let snapshot = snapshot.take();
+                        // The variables are those of the restored snapshot until a game has been
+                        // connected, and of the last connected game after that
+                        let previous_game_id = snapshot
+                            .as_ref()
+                            .map(|snapshot| &snapshot.game_id)
+                            .or(snapshot_game.as_ref().map(|(game_id, _)| game_id));
+                        match previous_game_id {
+                            Some(previous_game_id) if *previous_game_id != game_id => {
+                                println!(
+                                    "Discarding variables of {}, since {} is running",
+                                    previous_game_id, game_id
+                                );
+                                raw_variable_store.clear();
+                                removed_variables = variable_store.clear();
+                            }
+                            _ => {
+                                // Persistent data only makes sense to the interface that wrote it
+                                if let Some(snapshot) =
+                                    snapshot.filter(|snapshot| snapshot.interface == name)
+                                {
+                                    lua_interface
+                                        .restore_persistent_data(&snapshot.persistent_data)
+                                        .unwrap_or_else(|err| eprintln!("{}", err));
+                                }
+                            }
+                        }
+                        snapshot_game = Some((game_id, name.clone()));
+                        saved_persistent_data = None;
+                    }
+
+                    if let Some((previous_name, data)) = reloaded_persistent_data.take() {
+                        if previous_name == name {
+                            lua_interface
+                                .restore_persistent_data(&data)
+                                .unwrap_or_else(|err| eprintln!("{}", err));
+                        }
+                    }
                 }
                 Err(_) => {
                     println!("No interface found for this game");
@@ -205,30 +403,110 @@ fn main() -> Result<(), Box<dyn Error>> {
             };
         }
 
-        let changes = match lua_interface.run_game_watcher() {
+        // Variables written by custom commands since the last update, which are dropped if the
+        // game is disconnected before they can be applied
+        let pending_operations = mem::take(&mut command_operations);
+        let updates = match lua_interface.run_game_watcher() {
             Some(Ok(updates)) => updates,
             Some(Err(e)) => {
                 eprintln!("{}", e);
                 println!("Disconnected");
+                if let Some(event_log) = &mut event_log {
+                    event_log.disconnected(&e.to_string());
+                }
+                thread::sleep(CONNECTION_ATTEMPT_INTERVAL);
                 continue;
             }
             None => {
                 println!("Disconnected");
+                if let Some(event_log) = &mut event_log {
+                    event_log.disconnected("no interface");
+                }
                 continue;
             }
-        }
-        .into_iter()
-        .filter_map(|(k, res)| match res {
-            Ok(v) => Some((k, v)),
-            Err(e) => {
-                eprintln!("{}", e);
-                None
+        };
+        let mut raw_changes = pending_operations
+            .into_iter()
+            .chain(updates.operations)
+            .flat_map(|operation| match operation {
+                VariableOperation::Write(name, Ok(value)) => {
+                    raw_variable_store.update_variable(&name, value)
+                }
+                VariableOperation::Write(_, Err(e)) => {
+                    eprintln!("{}", e);
+                    Vec::new()
+                }
+                VariableOperation::Delete(name) => raw_variable_store.delete_variable(&name),
+                VariableOperation::ClearPrefix(prefix) => raw_variable_store.clear_prefix(&prefix),
+            })
+            .collect::<Vec<_>>();
+        let derived_changes = if recompute_derived {
+            recompute_derived = false;
+            compute_all_derived_variables(
+                &lua_interface,
+                &derived_variables,
+                &mut raw_variable_store,
+            )
+        } else {
+            update_derived_variables(
+                &lua_interface,
+                &derived_variables,
+                &mut raw_variable_store,
+                &raw_changes,
+            )
+        };
+        raw_changes.extend(derived_changes);
+        let filtered_changes = variable_filter.filter(
+            raw_changes,
+            |name| variable_store.get(name),
+            updates.loading,
+            Instant::now(),
+        );
+        let changes = removed_variables
+            .into_iter()
+            .chain(
+                filtered_changes
+                    .into_iter()
+                    .flat_map(|(name, value)| variable_store.apply(&name, value)),
+            )
+            .inspect(|change| {
+                println!(":{} = {}", change.name, change.value());
+                if let Some(event_log) = &mut event_log {
+                    event_log.variable_changed(change, split_slot(&change.name, &slots).0);
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if let (Some(path), Some((game_id, interface))) = (&options.snapshot, &snapshot_game) {
+            match lua_interface.persistent_data() {
+                Some(Ok(persistent_data))
+                    if !changes.is_empty()
+                        || saved_persistent_data.as_ref() != Some(&persistent_data) =>
+                {
+                    let snapshot = Snapshot {
+                        game_id: game_id.clone(),
+                        interface: interface.clone(),
+                        variables: variable_store
+                            .variable_values()
+                            .map(|(name, value)| (name.to_owned(), value.clone()))
+                            .collect(),
+                        persistent_data: persistent_data.clone(),
+                    };
+                    match snapshot.save(path) {
+                        Ok(()) => saved_persistent_data = Some(persistent_data),
+                        Err(err) => eprintln!("Couldn't save snapshot: {}", err),
+                    }
+                }
+                Some(Err(err)) => eprintln!("{}", err),
+                _ => {}
             }
-        })
-        .filter(|(name, value)| variable_store.update_variable(&name, value.clone()))
-        .inspect(|(name, value)| println!(":{} = {}", name, value))
-        .map(|(name, value)| ServerCommand::var(&name, value))
-        .collect::<Vec<_>>();
+        }
+
+        let changes = changes
+            .iter()
+            .flat_map(|change| variable_map.map(&change.name, &change.value()))
+            .map(|(name, value)| var_command(&name, value, &slots))
+            .collect::<Vec<_>>();
 
         // FIXME: Operations are entirely skipped if they block, which could be a problem for Sync responses.
         // Unsure how to fix without more threads.
@@ -240,19 +518,71 @@ fn main() -> Result<(), Box<dyn Error>> {
                 Ok(messages) => {
                     for message in messages {
                         match message {
-                            Ok(ClientCommand::Sync(_)) => {
-                                if !sent_variables {
-                                    replies.extend_from_slice(cache_variables.get_or_insert_with(
-                                        || {
-                                            variable_store
-                                                .variable_values()
-                                                .map(|(name, value)| {
-                                                    ServerCommand::var(name, value.clone())
+                            Ok(ClientCommand::Sync(command)) => match command.path() {
+                                Some(path) => {
+                                    // Paths name variables as clients see them, after mapping
+                                    let path = normalize_path(path);
+                                    replies.extend(
+                                        variable_store
+                                            .variable_values()
+                                            .flat_map(|(name, value)| variable_map.map(name, value))
+                                            .filter(|(name, _)| is_in_subtree(name, &path))
+                                            .map(|(name, value)| var_command(&name, value, &slots))
+                                            .filter(|command| client.is_subscribed(command)),
+                                    );
+                                }
+                                None => {
+                                    if !sent_variables {
+                                        replies.extend(
+                                            cache_variables
+                                                .get_or_insert_with(|| {
+                                                    variable_store
+                                                        .variable_values()
+                                                        .flat_map(|(name, value)| {
+                                                            variable_map.map(name, value)
+                                                        })
+                                                        .map(|(name, value)| {
+                                                            var_command(&name, value, &slots)
+                                                        })
+                                                        .collect()
                                                 })
-                                                .collect()
-                                        },
+                                                .iter()
+                                                .filter(|command| client.is_subscribed(command))
+                                                .cloned(),
+                                        );
+                                        sent_variables = true;
+                                    }
+                                }
+                            },
+                            Ok(ClientCommand::Subscribe(command)) => {
+                                client.subscribe(command.into_subscription());
+                            }
+                            Ok(ClientCommand::ReadMemory(command)) => {
+                                if !options.enable_read_memory {
+                                    replies.push(ServerCommand::error_reply(
+                                        "ReadMemory",
+                                        ErrorReplyReason::UnknownCmd,
                                     ));
-                                    sent_variables = true;
+                                    continue;
+                                }
+                                match lua_interface.read_memory(&command) {
+                                    Ok(values) => {
+                                        replies.push(ServerCommand::reply("ReadMemory", values))
+                                    }
+                                    Err(error_reply) => {
+                                        replies.push(ServerCommand::ErrorReply(error_reply))
+                                    }
+                                }
+                            }
+                            Ok(ClientCommand::Custom(command)) => {
+                                match lua_interface.run_command(&command) {
+                                    Ok(update) => {
+                                        command_operations.extend(update.operations);
+                                        replies.extend(update.reply);
+                                    }
+                                    Err(error_reply) => {
+                                        replies.push(ServerCommand::ErrorReply(error_reply))
+                                    }
                                 }
                             }
                             Err(error_reply) => {
@@ -263,13 +593,43 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
                 Err(err) => {
                     if err.kind() != ErrorKind::WouldBlock {
-                        eprintln!("{}", err);
+                        eprintln!(
+                            "{} failed to receive, disconnecting: {}",
+                            client_name(client),
+                            err
+                        );
                         client.shutdown().ok();
                     }
                 }
             };
+            if client.connected() {
+                match client.keep_alive(CLIENT_PING_INTERVAL, CLIENT_PONG_TIMEOUT) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        println!(
+                            "{} did not respond to ping, disconnecting",
+                            client_name(client)
+                        );
+                        continue;
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "{} failed to ping, disconnecting: {}",
+                            client_name(client),
+                            err
+                        );
+                        client.shutdown().ok();
+                        continue;
+                    }
+                }
+            }
             if !sent_variables {
-                replies.extend_from_slice(&changes);
+                replies.extend(
+                    changes
+                        .iter()
+                        .filter(|command| client.is_subscribed(command))
+                        .cloned(),
+                );
             }
             if client.connected() && !replies.is_empty() {
                 client
@@ -289,7 +649,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
             if let Some(info) = &cache_info {
                 new_client
-                    .send(&[info.clone()])
+                    .send(std::slice::from_ref(info))
                     .or_else(|_| new_client.shutdown())
                     .ok();
             } else {
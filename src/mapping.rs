@@ -0,0 +1,229 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use json::JsonValue;
+
+use crate::uat::variable::{is_in_subtree, join_path, normalize_path};
+
+/// A conversion applied to a variable's value before it is sent to clients. Null values, such as
+/// those of removed variables, are left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transform {
+    /// True for positive numbers, true, and non-empty strings and arrays
+    Bool,
+    /// The opposite of Bool
+    Not,
+    String,
+    Number,
+}
+
+impl Transform {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bool" => Some(Self::Bool),
+            "not" => Some(Self::Not),
+            "string" => Some(Self::String),
+            "number" => Some(Self::Number),
+            _ => None,
+        }
+    }
+
+    fn apply(self, value: &JsonValue) -> JsonValue {
+        let truthy = || match value {
+            JsonValue::Boolean(value) => *value,
+            JsonValue::Short(_) | JsonValue::String(_) => !value.is_empty(),
+            JsonValue::Array(array) => !array.is_empty(),
+            JsonValue::Number(_) => value.as_f64().is_some_and(|number| number > 0.0),
+            _ => false,
+        };
+        match (self, value) {
+            (_, JsonValue::Null) => JsonValue::Null,
+            (Self::Bool, _) => truthy().into(),
+            (Self::Not, _) => (!truthy()).into(),
+            (Self::String, JsonValue::Short(_) | JsonValue::String(_)) => value.clone(),
+            (Self::String, _) => value.dump().into(),
+            (Self::Number, JsonValue::Boolean(value)) => (*value as u8).into(),
+            (Self::Number, _) => match value.as_str() {
+                Some(string) => string
+                    .trim()
+                    .parse::<f64>()
+                    .map_or(JsonValue::Null, JsonValue::from),
+                None => value.as_number().map_or(JsonValue::Null, JsonValue::from),
+            },
+        }
+    }
+}
+
+/// A name a variable is sent to clients under.
+#[derive(Debug, Clone)]
+struct Target {
+    path: String,
+    transform: Option<Transform>,
+}
+
+impl Target {
+    fn parse(source: &str, json: &JsonValue) -> io::Result<Self> {
+        let invalid = |message: String| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("variable map entry {source:?} {message}"),
+            )
+        };
+        if let Some(path) = json.as_str() {
+            return Ok(Self {
+                path: normalize_path(path),
+                transform: None,
+            });
+        }
+        if !json.is_object() {
+            return Err(invalid(format!("has invalid target {}", json.dump())));
+        }
+        let path = match &json["name"] {
+            JsonValue::Null => source.to_owned(),
+            name => name
+                .as_str()
+                .map(normalize_path)
+                .ok_or_else(|| invalid(format!("has invalid name {}", name.dump())))?,
+        };
+        let transform = match &json["transform"] {
+            JsonValue::Null => None,
+            transform => Some(
+                transform
+                    .as_str()
+                    .and_then(Transform::from_name)
+                    .ok_or_else(|| {
+                        invalid(format!("has unknown transform {}", transform.dump()))
+                    })?,
+            ),
+        };
+        Ok(Self { path, transform })
+    }
+}
+
+/// Renames, drops, aliases and transforms variables on their way to clients, so one interface can
+/// serve tracker packs that expect different names for the same thing.
+#[derive(Debug, Clone, Default)]
+pub struct VariableMap {
+    /// Targets for the variables at or beneath each path, longest path first. An empty list drops
+    /// the variables.
+    rules: Vec<(String, Vec<Target>)>,
+}
+
+impl VariableMap {
+    /// Load a map from a JSON object keyed by variable path. Each value is a new name, null to drop
+    /// the variables, an object with an optional `name` and `transform`, or an array of names or
+    /// objects to send the variables under several names.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(data: &str) -> io::Result<Self> {
+        let json = json::parse(data).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        if !json.is_object() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "variable map must be an object",
+            ));
+        }
+        let mut rules = json
+            .entries()
+            .map(|(source, targets)| {
+                let source = normalize_path(source);
+                let targets = match targets {
+                    JsonValue::Null => Ok(Vec::new()),
+                    JsonValue::Array(targets) => targets
+                        .iter()
+                        .map(|target| Target::parse(&source, target))
+                        .collect(),
+                    target => Target::parse(&source, target).map(|target| vec![target]),
+                }?;
+                Ok((source, targets))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        rules.sort_by_key(|(path, _)| usize::MAX - path.len());
+        Ok(Self { rules })
+    }
+
+    /// The names and values a variable is sent to clients as. Variables without a rule are sent
+    /// unchanged.
+    pub fn map(&self, name: &str, value: &JsonValue) -> Vec<(String, JsonValue)> {
+        let Some((source, targets)) = self
+            .rules
+            .iter()
+            .find(|(path, _)| is_in_subtree(name, path))
+        else {
+            return vec![(name.to_owned(), value.clone())];
+        };
+        // Variables beneath the rule's path keep their place beneath the new path
+        let rest = &name[source.len()..];
+        targets
+            .iter()
+            .map(|target| {
+                let value = match target.transform {
+                    Some(transform) => transform.apply(value),
+                    None => value.clone(),
+                };
+                (join_path(&target.path, rest), value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(rules: &str, name: &str, value: JsonValue) -> Vec<(String, JsonValue)> {
+        VariableMap::parse(rules).unwrap().map(name, &value)
+    }
+
+    #[test]
+    fn rules_rename_drop_and_alias() {
+        let rules = r#"{
+            "/inventory/": "items",
+            "inventory/Missile": ["Missiles", {"name": "Has Missiles", "transform": "bool"}],
+            "debug": null
+        }"#;
+        assert_eq!(
+            map(rules, "inventory/Bombs", 1.into()),
+            [("items/Bombs".into(), 1.into())]
+        );
+        assert_eq!(
+            map(rules, "inventory/Missile", 5.into()),
+            [
+                ("Missiles".into(), 5.into()),
+                ("Has Missiles".into(), true.into())
+            ]
+        );
+        assert_eq!(map(rules, "debug/x", 1.into()), []);
+        assert_eq!(
+            map(rules, "inventory2", 1.into()),
+            [("inventory2".into(), 1.into())]
+        );
+    }
+
+    #[test]
+    fn transforms() {
+        let apply = |transform: &str, value: JsonValue| {
+            Transform::from_name(transform).unwrap().apply(&value)
+        };
+        assert_eq!(apply("bool", 0.into()), false);
+        assert_eq!(apply("bool", "".into()), false);
+        assert_eq!(apply("not", JsonValue::new_array()), true);
+        assert_eq!(apply("string", 5.into()), "5");
+        assert_eq!(apply("number", " 2.5 ".into()), 2.5);
+        assert_eq!(apply("number", true.into()), 1);
+        assert_eq!(apply("number", "many".into()), JsonValue::Null);
+        assert_eq!(apply("not", JsonValue::Null), JsonValue::Null);
+    }
+
+    #[test]
+    fn invalid_maps_are_rejected() {
+        assert!(VariableMap::parse("[]").is_err());
+        assert!(VariableMap::parse(r#"{"a": 5}"#).is_err());
+        assert!(VariableMap::parse(r#"{"a": {"transform": "upper"}}"#).is_err());
+    }
+}
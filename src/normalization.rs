@@ -0,0 +1,116 @@
+use json::JsonValue;
+
+/// Integers beyond this can't all be represented by the doubles most JSON readers use.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// How to send integers that are too large to be read exactly as a double.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LargeIntegers {
+    /// Send them as strings of their decimal digits.
+    #[default]
+    String,
+    /// Send them as numbers, even if readers lose precision.
+    Number,
+}
+
+/// How to handle NaN and infinite numbers, which can't be represented in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonFinite {
+    #[default]
+    Null,
+    /// Treat them as an error in the script.
+    Error,
+}
+
+/// Rules for turning numbers from scripts into values sent to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Normalization {
+    pub large_integers: LargeIntegers,
+    pub non_finite: NonFinite,
+    /// Number of decimal places to round floats to, so that noise in the lowest digits doesn't
+    /// change the value every time it's read.
+    pub float_precision: Option<i32>,
+}
+
+impl Normalization {
+    /// Keep values as they are, for data that's only read back by the bridge.
+    pub const LOSSLESS: Self = Self {
+        large_integers: LargeIntegers::Number,
+        non_finite: NonFinite::Null,
+        float_precision: None,
+    };
+
+    pub fn integer(&self, integer: i64) -> JsonValue {
+        match self.large_integers {
+            LargeIntegers::String if !(-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&integer) => {
+                integer.to_string().into()
+            }
+            _ => integer.into(),
+        }
+    }
+
+    /// Returns None for a number that isn't finite if that should be treated as an error.
+    pub fn float(&self, float: f64) -> Option<JsonValue> {
+        if !float.is_finite() {
+            return match self.non_finite {
+                NonFinite::Null => Some(JsonValue::Null),
+                NonFinite::Error => None,
+            };
+        }
+        Some(match self.float_precision {
+            Some(precision) => {
+                let scale = 10f64.powi(precision);
+                let scaled = float * scale;
+                if scaled.is_finite() {
+                    (scaled.round() / scale).into()
+                } else {
+                    float.into()
+                }
+            }
+            None => float.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_integers_become_strings() {
+        let normalization = Normalization::default();
+        assert_eq!(normalization.integer(MAX_SAFE_INTEGER), MAX_SAFE_INTEGER);
+        assert_eq!(
+            normalization.integer(-MAX_SAFE_INTEGER - 1),
+            "-9007199254740992"
+        );
+        assert_eq!(normalization.integer(i64::MAX), "9223372036854775807");
+        assert_eq!(Normalization::LOSSLESS.integer(i64::MAX), i64::MAX);
+    }
+
+    #[test]
+    fn non_finite_floats() {
+        let normalization = Normalization::default();
+        assert_eq!(normalization.float(f64::NAN), Some(JsonValue::Null));
+        let strict = Normalization {
+            non_finite: NonFinite::Error,
+            ..Default::default()
+        };
+        assert_eq!(strict.float(f64::INFINITY), None);
+        assert_eq!(strict.float(1.5), Some(1.5.into()));
+    }
+
+    #[test]
+    fn floats_are_rounded_to_precision() {
+        let normalization = Normalization {
+            float_precision: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(normalization.float(0.1 + 0.2), Some(0.3.into()));
+        assert_eq!(normalization.float(1.005e300), Some(1.005e300.into()));
+        assert_eq!(
+            Normalization::default().float(0.1 + 0.2),
+            Some((0.1 + 0.2).into())
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use json::JsonValue;
+
+use crate::uat::variable::{is_in_subtree, normalize_path, VariableChange};
+
+/// Rules for when changes to a variable are passed on to clients, to hide values that flicker
+/// while the game is loading.
+#[derive(Debug, Clone, Default)]
+pub struct VariablePolicy {
+    /// Only pass on a value once it has stayed the same for this long.
+    pub stable_for: Duration,
+    /// While the game watcher marks the game as loading, hold back null or removal of the
+    /// variable. It is passed on if it is still null once loading is over.
+    pub ignore_null: bool,
+    /// Never pass on a number lower than the current value.
+    pub monotonic: bool,
+    /// Pass on a change at most once in this period. Changes in between are held back, and the
+    /// latest one is passed on when the period is over.
+    pub min_interval: Duration,
+}
+
+impl VariablePolicy {
+    /// Whether a change from the current value should be thrown away rather than waited on.
+    fn rejects(&self, current: Option<&JsonValue>, new: Option<&JsonValue>) -> bool {
+        match (current, new) {
+            (Some(current), Some(new)) if self.monotonic => {
+                match (current.as_f64(), new.as_f64()) {
+                    (Some(current), Some(new)) => new < current,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+fn find_policy<'a>(
+    policies: &'a [(String, VariablePolicy)],
+    name: &str,
+) -> Option<&'a VariablePolicy> {
+    policies
+        .iter()
+        .find(|(path, _)| is_in_subtree(name, path))
+        .map(|(_, policy)| policy)
+}
+
+#[derive(Debug, Clone)]
+struct PendingChange {
+    value: Option<JsonValue>,
+    since: Instant,
+}
+
+/// Holds back changes to variables according to their policies. Changes go in as they come from
+/// the game watcher, and come out when they should be passed on to clients.
+#[derive(Debug, Clone, Default)]
+pub struct VariableFilter {
+    policies: Vec<(String, VariablePolicy)>,
+    pending: BTreeMap<String, PendingChange>,
+    last_passed: HashMap<String, Instant>,
+}
+
+impl VariableFilter {
+    /// Policies are keyed by a variable path, and apply to the variables at or beneath it. Where
+    /// several apply, the one with the longest path is used.
+    pub fn new(policies: impl IntoIterator<Item = (String, VariablePolicy)>) -> Self {
+        let mut policies = policies
+            .into_iter()
+            .map(|(path, policy)| (normalize_path(&path), policy))
+            .collect::<Vec<_>>();
+        policies.sort_by_key(|(path, _)| usize::MAX - path.len());
+        Self {
+            policies,
+            ..Default::default()
+        }
+    }
+
+    /// Take in changes and return the values to pass on, as `(name, value)` pairs where a value of
+    /// None removes the variable. `current` looks up the value clients currently have, and
+    /// `loading` is whether the game watcher marked the game as loading.
+    pub fn filter<'a>(
+        &mut self,
+        changes: impl IntoIterator<Item = VariableChange>,
+        current: impl Fn(&str) -> Option<&'a JsonValue>,
+        loading: bool,
+        now: Instant,
+    ) -> Vec<(String, Option<JsonValue>)> {
+        let mut passed = Vec::new();
+        for change in changes {
+            if find_policy(&self.policies, &change.name).is_some() {
+                self.pending.insert(
+                    change.name,
+                    PendingChange {
+                        value: change.new_value,
+                        since: now,
+                    },
+                );
+            } else {
+                passed.push((change.name, change.new_value));
+            }
+        }
+
+        let mut resolved = Vec::new();
+        for (name, pending) in &self.pending {
+            let Some(policy) = find_policy(&self.policies, name) else {
+                resolved.push(name.clone());
+                continue;
+            };
+            let current = current(name);
+            if current == pending.value.as_ref() || policy.rejects(current, pending.value.as_ref())
+            {
+                resolved.push(name.clone());
+                continue;
+            }
+            if loading
+                && policy.ignore_null
+                && pending.value.as_ref().is_none_or(JsonValue::is_null)
+            {
+                continue;
+            }
+            let stable = now.duration_since(pending.since) >= policy.stable_for;
+            let rate_allows = self
+                .last_passed
+                .get(name)
+                .is_none_or(|last| now.duration_since(*last) >= policy.min_interval);
+            if stable && rate_allows {
+                passed.push((name.clone(), pending.value.clone()));
+                self.last_passed.insert(name.clone(), now);
+                resolved.push(name.clone());
+            }
+        }
+        for name in resolved {
+            self.pending.remove(&name);
+        }
+        passed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn change(name: &str, new_value: Option<JsonValue>) -> VariableChange {
+        VariableChange {
+            name: name.to_owned(),
+            old_value: None,
+            new_value,
+        }
+    }
+
+    fn filter(policy: VariablePolicy) -> VariableFilter {
+        VariableFilter::new([("/inventory/".to_owned(), policy)])
+    }
+
+    #[test]
+    fn changes_without_a_policy_pass_through() {
+        let mut filter = filter(VariablePolicy {
+            stable_for: Duration::from_secs(1),
+            ..Default::default()
+        });
+        let passed = filter.filter(
+            [change("inventory2", Some(1.into()))],
+            |_| None,
+            false,
+            Instant::now(),
+        );
+        assert_eq!(passed, [("inventory2".to_owned(), Some(1.into()))]);
+    }
+
+    #[test]
+    fn stable_for_waits_for_the_value_to_settle() {
+        let mut filter = filter(VariablePolicy {
+            stable_for: Duration::from_secs(1),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let changes = [change("inventory/Missile", Some(5.into()))];
+        assert!(filter.filter(changes, |_| None, false, start).is_empty());
+        let later = start + Duration::from_secs(1);
+        assert_eq!(
+            filter.filter([], |_| None, false, later),
+            [("inventory/Missile".to_owned(), Some(5.into()))]
+        );
+        assert!(filter.filter([], |_| None, false, later).is_empty());
+    }
+
+    #[test]
+    fn ignore_null_holds_nulls_back_while_loading() {
+        let mut filter = filter(VariablePolicy {
+            ignore_null: true,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let current = JsonValue::from(5);
+        let changes = [change("inventory/Missile", None)];
+        assert!(filter
+            .filter(changes, |_| Some(&current), true, now)
+            .is_empty());
+        assert!(filter.filter([], |_| Some(&current), true, now).is_empty());
+        assert_eq!(
+            filter.filter([], |_| Some(&current), false, now),
+            [("inventory/Missile".to_owned(), None)]
+        );
+    }
+
+    #[test]
+    fn monotonic_rejects_decreases() {
+        let mut filter = filter(VariablePolicy {
+            monotonic: true,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let current = JsonValue::from(5);
+        let changes = [
+            change("inventory/Missile", Some(4.into())),
+            change("inventory/Bombs", Some(6.into())),
+        ];
+        assert_eq!(
+            filter.filter(changes, |_| Some(&current), false, now),
+            [("inventory/Bombs".to_owned(), Some(6.into()))]
+        );
+        assert!(filter.filter([], |_| Some(&current), false, now).is_empty());
+    }
+
+    #[test]
+    fn min_interval_passes_the_latest_change_afterwards() {
+        let mut filter = filter(VariablePolicy {
+            min_interval: Duration::from_secs(5),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let name = "inventory/Missile";
+        assert_eq!(
+            filter.filter([change(name, Some(1.into()))], |_| None, false, start),
+            [(name.to_owned(), Some(1.into()))]
+        );
+        let soon = start + Duration::from_secs(1);
+        assert!(filter
+            .filter([change(name, Some(2.into()))], |_| None, false, soon)
+            .is_empty());
+        assert!(filter
+            .filter([change(name, Some(3.into()))], |_| None, false, soon)
+            .is_empty());
+        assert_eq!(
+            filter.filter([], |_| None, false, start + Duration::from_secs(5)),
+            [(name.to_owned(), Some(3.into()))]
+        );
+    }
+
+    #[test]
+    fn longest_policy_path_wins() {
+        let filter = VariableFilter::new([
+            ("inventory".to_owned(), VariablePolicy::default()),
+            (
+                "inventory/Missile".to_owned(),
+                VariablePolicy {
+                    monotonic: true,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        assert!(find_policy(&filter.policies, "inventory/Missile/Capacity")
+            .is_some_and(|p| p.monotonic));
+        assert!(find_policy(&filter.policies, "inventory/Bombs").is_some_and(|p| !p.monotonic));
+        assert!(find_policy(&filter.policies, "other").is_none());
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt::Display;
+
+use json::JsonValue;
+
+use crate::uat::variable::{is_in_subtree, join_path, normalize_path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaType {
+    Boolean,
+    Number,
+    Integer,
+    String,
+    Array,
+}
+
+impl SchemaType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "boolean" => Some(Self::Boolean),
+            "number" => Some(Self::Number),
+            "integer" => Some(Self::Integer),
+            "string" => Some(Self::String),
+            "array" => Some(Self::Array),
+            _ => None,
+        }
+    }
+
+    fn matches(self, value: &JsonValue) -> bool {
+        match self {
+            Self::Boolean => value.is_boolean(),
+            Self::Number => value.is_number(),
+            Self::Integer => value.as_f64().is_some_and(|number| number.fract() == 0.0),
+            Self::String => value.is_string(),
+            Self::Array => value.is_array(),
+        }
+    }
+
+    /// Convert a value to this type, if there's an obvious way to.
+    fn coerce(self, value: &JsonValue) -> Option<JsonValue> {
+        match (self, value) {
+            (Self::Boolean, JsonValue::Number(_)) => Some((value.as_f64()? != 0.0).into()),
+            (Self::Boolean, _) => match value.as_str()?.trim() {
+                "true" => Some(true.into()),
+                "false" => Some(false.into()),
+                _ => None,
+            },
+            (Self::Number | Self::Integer, JsonValue::Boolean(value)) => {
+                Some((*value as u8).into())
+            }
+            (Self::Number | Self::Integer, _) => {
+                let number = match value.as_f64() {
+                    Some(number) => number,
+                    None => value.as_str()?.trim().parse().ok()?,
+                };
+                Some(match self {
+                    Self::Integer => number.trunc().into(),
+                    _ => number.into(),
+                })
+            }
+            (Self::String, JsonValue::Number(_) | JsonValue::Boolean(_)) => {
+                Some(value.dump().into())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for SchemaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Boolean => "boolean",
+            Self::Number => "number",
+            Self::Integer => "integer",
+            Self::String => "string",
+            Self::Array => "array",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What to do with values that don't match their schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Report the value, but write it anyway.
+    #[default]
+    Warn,
+    /// Convert the value to match the schema, and reject it if that isn't possible.
+    Coerce,
+    /// Report the value and don't write it.
+    Reject,
+}
+
+/// The values a variable is allowed to have. Null is always allowed, so variables can be cleared.
+#[derive(Debug, Clone, Default)]
+pub struct VariableSchema {
+    pub value_type: Option<SchemaType>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// The strings the variable can be.
+    pub values: Option<Vec<String>>,
+}
+
+impl VariableSchema {
+    /// Describe what's wrong with a value, if anything.
+    fn violation(&self, value: &JsonValue) -> Option<String> {
+        if let Some(value_type) = self.value_type {
+            if !value_type.matches(value) {
+                return Some(format!("expected {}, got {}", value_type, value.dump()));
+            }
+        }
+        if let Some(number) = value.as_f64() {
+            if self.min.is_some_and(|min| number < min) || self.max.is_some_and(|max| number > max)
+            {
+                let bound = |bound: Option<f64>| bound.map_or("".into(), |bound| bound.to_string());
+                return Some(format!(
+                    "{} is outside of {}..{}",
+                    number,
+                    bound(self.min),
+                    bound(self.max)
+                ));
+            }
+        }
+        if let (Some(values), Some(string)) = (&self.values, value.as_str()) {
+            if !values.iter().any(|allowed| allowed == string) {
+                return Some(format!("{:?} is not one of {:?}", string, values));
+            }
+        }
+        None
+    }
+
+    /// Convert a value to match the schema, clamping numbers to the range.
+    fn coerce(&self, value: &JsonValue) -> Option<JsonValue> {
+        let mut value = match self.value_type {
+            Some(value_type) if !value_type.matches(value) => value_type.coerce(value)?,
+            _ => value.clone(),
+        };
+        if let Some(number) = value.as_f64() {
+            let number = self.max.map_or(number, |max| number.min(max));
+            let number = self.min.map_or(number, |min| number.max(min));
+            value = number.into();
+        }
+        self.violation(&value).is_none().then_some(value)
+    }
+}
+
+/// Schemas for an interface's variables, which values written by the script are checked against.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    /// Schemas for the variables at or beneath each path, longest path first
+    entries: Vec<(String, VariableSchema)>,
+    strictness: Strictness,
+}
+
+impl Schema {
+    pub fn new(
+        entries: impl IntoIterator<Item = (String, VariableSchema)>,
+        strictness: Strictness,
+    ) -> Self {
+        let mut entries = entries
+            .into_iter()
+            .map(|(path, schema)| (normalize_path(&path), schema))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(path, _)| usize::MAX - path.len());
+        Self {
+            entries,
+            strictness,
+        }
+    }
+
+    /// Check a value written at a path against the schema. Objects are checked member by member,
+    /// the same way they're split into variables. Returns the value to write, or None if the
+    /// write should be rejected, along with a description of each violation.
+    pub fn validate(&self, name: &str, mut value: JsonValue) -> (Option<JsonValue>, Vec<String>) {
+        let mut violations = Vec::new();
+        if self.entries.is_empty() {
+            return (Some(value), violations);
+        }
+        let valid = self.validate_at(&normalize_path(name), &mut value, &mut violations);
+        let accepted = match self.strictness {
+            Strictness::Warn => true,
+            Strictness::Coerce => valid,
+            Strictness::Reject => violations.is_empty(),
+        };
+        (accepted.then_some(value), violations)
+    }
+
+    /// Returns false if a value couldn't be coerced to match.
+    fn validate_at(&self, path: &str, value: &mut JsonValue, violations: &mut Vec<String>) -> bool {
+        match value {
+            JsonValue::Object(object) if !object.is_empty() => {
+                let mut valid = true;
+                for (key, value) in object.iter_mut() {
+                    valid &= self.validate_at(&join_path(path, key), value, violations);
+                }
+                valid
+            }
+            JsonValue::Null => true,
+            _ => {
+                let Some((_, schema)) = self
+                    .entries
+                    .iter()
+                    .find(|(schema_path, _)| is_in_subtree(path, schema_path))
+                else {
+                    return true;
+                };
+                let Some(violation) = schema.violation(value) else {
+                    return true;
+                };
+                violations.push(format!("{}: {}", path, violation));
+                if self.strictness != Strictness::Coerce {
+                    return true;
+                }
+                match schema.coerce(value) {
+                    Some(coerced) => {
+                        *value = coerced;
+                        true
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use json::object;
+
+    use super::*;
+
+    fn schema(strictness: Strictness) -> Schema {
+        Schema::new(
+            [
+                (
+                    "inventory".to_owned(),
+                    VariableSchema {
+                        value_type: Some(SchemaType::Integer),
+                        min: Some(0.0),
+                        max: Some(255.0),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "/inventory/Name/".to_owned(),
+                    VariableSchema {
+                        value_type: Some(SchemaType::String),
+                        values: Some(vec!["Samus".to_owned()]),
+                        ..Default::default()
+                    },
+                ),
+            ],
+            strictness,
+        )
+    }
+
+    #[test]
+    fn warn_writes_invalid_values() {
+        let (value, violations) =
+            schema(Strictness::Warn).validate("inventory/Missile", 300.into());
+        assert_eq!(value, Some(300.into()));
+        assert_eq!(violations, ["inventory/Missile: 300 is outside of 0..255"]);
+    }
+
+    #[test]
+    fn reject_drops_invalid_values() {
+        let schema = schema(Strictness::Reject);
+        assert_eq!(schema.validate("inventory/Missile", "5".into()).0, None);
+        assert_eq!(
+            schema.validate("inventory/Missile", 5.into()).0,
+            Some(5.into())
+        );
+        assert_eq!(schema.validate("other", "5".into()).0, Some("5".into()));
+        assert_eq!(
+            schema.validate("inventory/Missile", JsonValue::Null),
+            (Some(JsonValue::Null), vec![])
+        );
+    }
+
+    #[test]
+    fn coerce_converts_and_clamps() {
+        let schema = schema(Strictness::Coerce);
+        assert_eq!(
+            schema.validate("inventory/Missile", " 5 ".into()).0,
+            Some(5.into())
+        );
+        assert_eq!(
+            schema.validate("inventory/Missile", 2.7.into()).0,
+            Some(2.into())
+        );
+        assert_eq!(
+            schema.validate("inventory/Missile", (-3).into()).0,
+            Some(0.into())
+        );
+        assert_eq!(
+            schema.validate("inventory/Missile", true.into()).0,
+            Some(1.into())
+        );
+        assert_eq!(schema.validate("inventory/Missile", "many".into()).0, None);
+    }
+
+    #[test]
+    fn objects_are_checked_per_member_by_longest_path() {
+        let schema = schema(Strictness::Coerce);
+        let (value, violations) =
+            schema.validate("", object! {inventory: {Missile: "5", Name: "Samus"}});
+        assert_eq!(
+            value,
+            Some(object! {inventory: {Missile: 5, Name: "Samus"}})
+        );
+        assert_eq!(violations.len(), 1);
+
+        let (value, _) = schema.validate("inventory", object! {Missile: 5, Name: "Ridley"});
+        assert_eq!(value, None);
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Find the scripts in the search paths. Files are used as they are, and directories are searched
+/// for `.lua` files. Problems reading the search paths are returned rather than stopping the search.
+pub fn find_scripts(search_paths: &[PathBuf]) -> (Vec<PathBuf>, Vec<io::Error>) {
+    let mut scripts = Vec::new();
+    let mut errors = Vec::new();
+    for search_path in search_paths {
+        if search_path.is_file() {
+            scripts.push(search_path.clone());
+        } else if search_path.is_dir() {
+            let dir_entries = match search_path.read_dir() {
+                Ok(d) => d,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+            let mut dir_scripts = Vec::new();
+            for entry in dir_entries {
+                let file = match entry {
+                    Ok(f) => f,
+                    Err(err) => {
+                        errors.push(err);
+                        break;
+                    }
+                };
+                match file.file_type() {
+                    Ok(t) => {
+                        if !t.is_file() {
+                            continue;
+                        }
+                    }
+                    Err(_) => continue,
+                }
+                if !file.file_name().to_string_lossy().ends_with(".lua") {
+                    continue;
+                }
+                dir_scripts.push(file.path());
+            }
+            // Directory order isn't stable, so sort to run scripts in the same order each time
+            dir_scripts.sort();
+            scripts.extend(dir_scripts);
+        }
+    }
+    (scripts, errors)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+/// Watches the scripts in the search paths for changes by polling their modification times.
+pub struct ScriptWatcher {
+    search_paths: Vec<PathBuf>,
+    modified: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+impl ScriptWatcher {
+    pub fn new(search_paths: Vec<PathBuf>) -> Self {
+        let mut watcher = Self {
+            search_paths,
+            modified: BTreeMap::new(),
+        };
+        watcher.changed();
+        watcher
+    }
+
+    /// Check whether any script was added, removed or modified since the last check.
+    pub fn changed(&mut self) -> bool {
+        let (scripts, _) = find_scripts(&self.search_paths);
+        let modified = scripts
+            .into_iter()
+            .map(|path| {
+                let time = modified_time(&path);
+                (path, time)
+            })
+            .collect::<BTreeMap<_, _>>();
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use json::{object, JsonValue};
+
+/// The state of a game saved to disk, so it can be restored if the bridge is restarted.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub game_id: String,
+    pub interface: String,
+    pub variables: Vec<(String, JsonValue)>,
+    pub persistent_data: JsonValue,
+}
+
+impl Snapshot {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let data = fs::read_to_string(path)?;
+        let json = json::parse(&data).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        let invalid = |field: &str| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("snapshot has missing or invalid {field}"),
+            )
+        };
+        let string = |field: &str| {
+            json[field]
+                .as_str()
+                .map(str::to_owned)
+                .ok_or(invalid(field))
+        };
+        if !json["variables"].is_object() {
+            Err(invalid("variables"))?;
+        }
+        Ok(Self {
+            game_id: string("game_id")?,
+            interface: string("interface")?,
+            variables: json["variables"]
+                .entries()
+                .map(|(name, value)| (name.to_owned(), value.clone()))
+                .collect(),
+            persistent_data: json["persistent_data"].clone(),
+        })
+    }
+
+    /// Write the snapshot to a temporary file and move it into place, so that a crash while
+    /// saving can't leave a partially written snapshot behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut variables = JsonValue::new_object();
+        for (name, value) in &self.variables {
+            variables[name.as_str()] = value.clone();
+        }
+        let json = object! {
+            game_id: self.game_id.as_str(),
+            interface: self.interface.as_str(),
+            variables: variables,
+            persistent_data: self.persistent_data.clone(),
+        };
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, json::stringify_pretty(json, 2))?;
+        fs::rename(temp_path, path)
+    }
+}
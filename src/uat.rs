@@ -1,9 +1,11 @@
 use std::{
     io::{self, ErrorKind},
     net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
 };
 
 use command::{ClientCommand, ErrorReplyCommand, ErrorReplyReason, ServerCommand};
+use subscription::Subscription;
 use websocket::{
     server::{NoTlsAcceptor, WsServer},
     sync::Client as WsClient,
@@ -11,36 +13,72 @@ use websocket::{
 };
 
 pub mod command;
+pub mod subscription;
 pub mod variable;
 
 pub const UAT_PORT_MAIN: u16 = 65399;
 pub const UAT_PORT_BACKUP: u16 = 44444;
 pub const UAT_PROTOCOL_VERSION: i32 = 0;
 
-pub struct Server(WsServer<NoTlsAcceptor, TcpListener>);
+pub struct Server {
+    server: WsServer<NoTlsAcceptor, TcpListener>,
+    allowed_origins: Vec<String>,
+}
 
 pub struct Client {
     client: WsClient<TcpStream>,
+    address: Option<SocketAddr>,
     shut_down: bool,
+    last_received: Instant,
+    ping_sent: Option<Instant>,
+    subscription: Option<Subscription>,
 }
 
 impl Server {
-    pub fn new(addr: impl Into<IpAddr>) -> Result<Self, io::Error> {
+    /// Bind to the first available port in `ports`. Browser clients, which always send an `Origin`
+    /// header, are only accepted if their origin is in `allowed_origins` or it contains `*`.
+    pub fn new(
+        addr: impl Into<IpAddr>,
+        ports: &[u16],
+        allowed_origins: Vec<String>,
+    ) -> Result<Self, io::Error> {
         let addr = addr.into();
-        let addresses = [
-            SocketAddr::new(addr, UAT_PORT_MAIN),
-            SocketAddr::new(addr, UAT_PORT_BACKUP),
-        ];
+        let addresses = ports
+            .iter()
+            .map(|&port| SocketAddr::new(addr, port))
+            .collect::<Vec<_>>();
         let server = websocket::server::sync::Server::bind(addresses.as_slice())?;
-        Ok(Self(server))
+        Ok(Self {
+            server,
+            allowed_origins,
+        })
     }
 
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.0.local_addr()
+        self.server.local_addr()
+    }
+
+    fn origin_allowed(allowed_origins: &[String], origin: Option<&str>) -> bool {
+        match origin {
+            // Native clients like PopTracker don't send an origin
+            None => true,
+            Some(origin) => allowed_origins
+                .iter()
+                .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin)),
+        }
     }
 
     pub fn accept_clients(self) -> impl Iterator<Item = io::Result<Client>> {
-        self.0.into_iter().filter_map(Result::ok).map(|connection| {
+        let allowed_origins = self.allowed_origins;
+        self.server.filter_map(Result::ok).map(move |connection| {
+            if !Self::origin_allowed(&allowed_origins, connection.origin()) {
+                let origin = connection.origin().unwrap_or_default().to_owned();
+                connection.reject().map_err(|(_, err)| err)?;
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("rejected client from origin {origin}"),
+                ));
+            }
             let client = connection.accept().map_err(|(_, err)| err)?;
             Client::new(client)
         })
@@ -51,8 +89,12 @@ impl Client {
     fn new(client: WsClient<TcpStream>) -> io::Result<Self> {
         client.set_nonblocking(true)?;
         Ok(Self {
+            address: client.peer_addr().ok(),
             client,
             shut_down: false,
+            last_received: Instant::now(),
+            ping_sent: None,
+            subscription: None,
         })
     }
 
@@ -65,12 +107,35 @@ impl Client {
             }
             WebSocketError::IoError(err) => err,
             WebSocketError::Utf8Error(err) => io::Error::new(ErrorKind::InvalidData, err),
-            WebSocketError::Other(err) => io::Error::new(ErrorKind::Other, err),
+            WebSocketError::Other(err) => io::Error::other(err),
+        }
+    }
+
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.address
+    }
+
+    /// Only send this client variables matching `subscription`, or every variable if None.
+    pub fn subscribe(&mut self, subscription: Option<Subscription>) {
+        self.subscription = subscription;
+    }
+
+    /// Whether a command should be sent to this client given its subscription.
+    pub fn is_subscribed(&self, command: &ServerCommand) -> bool {
+        match (command, &self.subscription) {
+            (ServerCommand::Var(var), Some(subscription)) => subscription.matches(var.name()),
+            _ => true,
         }
     }
 
     pub fn receive(&mut self) -> io::Result<Vec<Result<ClientCommand, ErrorReplyCommand>>> {
-        let data = match self.client.recv_message() {
+        let message = self.client.recv_message();
+        if message.is_ok() {
+            // Any message shows the client is still there, not just a pong
+            self.last_received = Instant::now();
+            self.ping_sent = None;
+        }
+        let data = match message {
             Ok(OwnedMessage::Text(text)) => text,
             Ok(OwnedMessage::Ping(data)) => {
                 self.client
@@ -108,12 +173,38 @@ impl Client {
             .send_message(&Message::text(json::stringify(message)))
     }
 
+    /// Ping the client if nothing has been received from it in `interval`, and shut it down if it
+    /// hasn't responded to a ping within `timeout`. Returns whether the client is still connected.
+    pub fn keep_alive(&mut self, interval: Duration, timeout: Duration) -> io::Result<bool> {
+        match self.ping_sent {
+            Some(ping_sent) if ping_sent.elapsed() >= timeout => {
+                self.shutdown().ok();
+                Ok(false)
+            }
+            Some(_) => Ok(true),
+            None if self.last_received.elapsed() >= interval => {
+                match self
+                    .client
+                    .send_message(&Message::ping(Vec::new()))
+                    .map_err(Self::convert_websocket_error)
+                {
+                    Ok(()) => self.ping_sent = Some(Instant::now()),
+                    // The socket can't take the ping right now, so try again next time
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                    Err(err) => return Err(err),
+                }
+                Ok(true)
+            }
+            None => Ok(true),
+        }
+    }
+
     pub fn shutdown(&mut self) -> io::Result<()> {
         self.shut_down = true;
         self.client.shutdown()
     }
 
     pub fn connected(&self) -> bool {
-        return !self.shut_down;
+        !self.shut_down
     }
 }
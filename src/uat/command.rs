@@ -2,12 +2,13 @@ use std::fmt::Display;
 
 use json::{object, JsonValue};
 
-use crate::uat::UAT_PROTOCOL_VERSION;
+use crate::uat::{subscription::Subscription, UAT_PROTOCOL_VERSION};
 
 #[allow(unused)]
 #[derive(Debug)]
 pub struct SyncCommand {
     slot: Option<String>,
+    path: Option<String>,
 }
 
 #[allow(unused)]
@@ -17,7 +18,112 @@ impl SyncCommand {
     }
 
     pub fn with_slot(slot: Option<String>) -> Self {
-        Self { slot }
+        Self::with_path(slot, None)
+    }
+
+    /// As an extension, a Sync can ask for only the variables at or beneath a path.
+    pub fn with_path(slot: Option<String>, path: Option<String>) -> Self {
+        Self { slot, path }
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+}
+
+/// A command that isn't part of the UAT protocol, to be handled by the active game interface.
+#[derive(Debug)]
+pub struct CustomCommand {
+    name: String,
+    arguments: JsonValue,
+}
+
+impl CustomCommand {
+    pub fn new(name: &str, arguments: JsonValue) -> Self {
+        Self {
+            name: name.to_owned(),
+            arguments,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The fields of the command object, other than `cmd`.
+    pub fn arguments(&self) -> &JsonValue {
+        &self.arguments
+    }
+}
+
+/// An extension to only receive updates for some variables. Leaving out both prefixes and
+/// patterns subscribes to every variable again.
+#[derive(Debug)]
+pub struct SubscribeCommand {
+    prefixes: Option<Vec<String>>,
+    patterns: Option<Vec<String>>,
+}
+
+impl SubscribeCommand {
+    pub fn new(prefixes: Option<Vec<String>>, patterns: Option<Vec<String>>) -> Self {
+        Self { prefixes, patterns }
+    }
+
+    pub fn into_subscription(self) -> Option<Subscription> {
+        if self.prefixes.is_none() && self.patterns.is_none() {
+            None
+        } else {
+            Some(Subscription::new(
+                self.prefixes.unwrap_or_default(),
+                self.patterns.unwrap_or_default(),
+            ))
+        }
+    }
+}
+
+fn parse_string_list(
+    command: &str,
+    argument: &str,
+    value: &JsonValue,
+) -> Result<Option<Vec<String>>, ErrorReplyCommand> {
+    match value {
+        JsonValue::Null => Ok(None),
+        JsonValue::Array(array) => array
+            .iter()
+            .map(|item| item.as_str().map(str::to_owned))
+            .collect::<Option<Vec<_>>>()
+            .map(Some)
+            .ok_or_else(|| {
+                ErrorReplyCommand::with_argument_and_description(
+                    command,
+                    Some(argument),
+                    ErrorReplyReason::BadValue,
+                    Some("expected array of strings"),
+                )
+            }),
+        _ => Err(ErrorReplyCommand::with_argument_and_description(
+            command,
+            Some(argument),
+            ErrorReplyReason::BadValue,
+            Some("expected array of strings"),
+        )),
+    }
+}
+
+/// A debugging extension to read arbitrary memory from the game.
+#[derive(Debug)]
+pub struct ReadMemoryCommand {
+    reads: JsonValue,
+}
+
+impl ReadMemoryCommand {
+    pub fn new(reads: JsonValue) -> Self {
+        Self { reads }
+    }
+
+    /// An array of `[address, type, offset]` arrays, with the same meaning as in GameCube:Read().
+    pub fn reads(&self) -> &JsonValue {
+        &self.reads
     }
 }
 
@@ -25,6 +131,9 @@ impl SyncCommand {
 #[derive(Debug)]
 pub enum ClientCommand {
     Sync(SyncCommand),
+    Subscribe(SubscribeCommand),
+    ReadMemory(ReadMemoryCommand),
+    Custom(CustomCommand),
 }
 
 impl TryFrom<&JsonValue> for ClientCommand {
@@ -33,10 +142,22 @@ impl TryFrom<&JsonValue> for ClientCommand {
     fn try_from(value: &JsonValue) -> Result<Self, Self::Error> {
         if let JsonValue::Object(obj) = value {
             match obj["cmd"].as_str() {
-                Some("Sync") => Ok(Self::Sync(SyncCommand::with_slot(
+                Some("Sync") => Ok(Self::Sync(SyncCommand::with_path(
                     obj["slot"].as_str().map(String::from),
+                    obj["path"].as_str().map(String::from),
+                ))),
+                Some("Subscribe") => Ok(Self::Subscribe(SubscribeCommand::new(
+                    parse_string_list("Subscribe", "prefixes", &obj["prefixes"])?,
+                    parse_string_list("Subscribe", "patterns", &obj["patterns"])?,
+                ))),
+                Some("ReadMemory") => Ok(Self::ReadMemory(ReadMemoryCommand::new(
+                    obj["reads"].clone(),
                 ))),
-                Some(s) => Err(ErrorReplyCommand::new(s, ErrorReplyReason::UnknownCmd)),
+                Some(s) => {
+                    let mut arguments = value.clone();
+                    arguments.remove("cmd");
+                    Ok(Self::Custom(CustomCommand::new(s, arguments)))
+                }
                 None => Err(ErrorReplyCommand::with_description(
                     "",
                     ErrorReplyReason::MissingArgument,
@@ -81,18 +202,18 @@ impl InfoCommand {
     }
 }
 
-impl Into<JsonValue> for InfoCommand {
-    fn into(self) -> JsonValue {
+impl From<InfoCommand> for JsonValue {
+    fn from(command: InfoCommand) -> Self {
         let mut cmd = object! {
             cmd: "Info",
-            name: self.name,
-            version: self.version,
+            name: command.name,
+            version: command.version,
             protocol: UAT_PROTOCOL_VERSION,
         };
-        if let Some(features) = self.features {
+        if let Some(features) = command.features {
             cmd["features"] = JsonValue::from(features);
         }
-        if let Some(slots) = self.slots {
+        if let Some(slots) = command.slots {
             cmd["slots"] = JsonValue::from(slots);
         }
         cmd
@@ -118,22 +239,51 @@ impl VarCommand {
             slot,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
-impl Into<JsonValue> for VarCommand {
-    fn into(self) -> JsonValue {
+impl From<VarCommand> for JsonValue {
+    fn from(command: VarCommand) -> Self {
         let mut cmd = object! {
             cmd: "Var",
-            name: self.name,
-            value: self.value,
+            name: command.name,
+            value: command.value,
         };
-        if let Some(slot) = self.slot {
+        if let Some(slot) = command.slot {
             cmd["slot"] = JsonValue::from(slot);
         }
         cmd
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ReplyCommand {
+    name: String,
+    value: JsonValue,
+}
+
+impl ReplyCommand {
+    pub fn new(name: &str, value: JsonValue) -> Self {
+        Self {
+            name: name.to_owned(),
+            value,
+        }
+    }
+}
+
+impl From<ReplyCommand> for JsonValue {
+    fn from(command: ReplyCommand) -> Self {
+        object! {
+            cmd: "Reply",
+            name: command.name,
+            value: command.value,
+        }
+    }
+}
+
 #[allow(unused)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorReplyReason {
@@ -190,15 +340,18 @@ impl ErrorReplyCommand {
     }
 }
 
-impl Into<JsonValue> for ErrorReplyCommand {
-    fn into(self) -> JsonValue {
+impl From<ErrorReplyCommand> for JsonValue {
+    fn from(command: ErrorReplyCommand) -> Self {
         let mut value = object! {
-            name: self.name,
-            reason: self.reason.to_string(),
+            name: command.name,
+            reason: command.reason.to_string(),
         };
-        self.argument.map(|arg| value["argument"] = arg.into());
-        self.description
-            .map(|desc| value["description"] = desc.into());
+        if let Some(arg) = command.argument {
+            value["argument"] = arg.into();
+        }
+        if let Some(desc) = command.description {
+            value["description"] = desc.into();
+        }
         value
     }
 }
@@ -207,6 +360,7 @@ impl Into<JsonValue> for ErrorReplyCommand {
 pub enum ServerCommand {
     Info(InfoCommand),
     Var(VarCommand),
+    Reply(ReplyCommand),
     ErrorReply(ErrorReplyCommand),
 }
 
@@ -231,17 +385,22 @@ impl ServerCommand {
         Self::Var(VarCommand::with_slot(name, value, slot))
     }
 
+    pub fn reply(name: &str, value: JsonValue) -> Self {
+        Self::Reply(ReplyCommand::new(name, value))
+    }
+
     pub fn error_reply(name: &str, reason: ErrorReplyReason) -> Self {
         Self::ErrorReply(ErrorReplyCommand::new(name, reason))
     }
 }
 
-impl Into<JsonValue> for ServerCommand {
-    fn into(self) -> JsonValue {
-        match self {
-            Self::Info(cmd) => cmd.into(),
-            Self::Var(cmd) => cmd.into(),
-            Self::ErrorReply(cmd) => cmd.into(),
+impl From<ServerCommand> for JsonValue {
+    fn from(command: ServerCommand) -> Self {
+        match command {
+            ServerCommand::Info(cmd) => cmd.into(),
+            ServerCommand::Var(cmd) => cmd.into(),
+            ServerCommand::Reply(cmd) => cmd.into(),
+            ServerCommand::ErrorReply(cmd) => cmd.into(),
         }
     }
 }
@@ -0,0 +1,84 @@
+/// A set of variable names a client wants updates for.
+#[derive(Debug, Clone, Default)]
+pub struct Subscription {
+    prefixes: Vec<String>,
+    patterns: Vec<String>,
+}
+
+impl Subscription {
+    pub fn new(prefixes: Vec<String>, patterns: Vec<String>) -> Self {
+        Self { prefixes, patterns }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.prefixes
+            .iter()
+            .any(|prefix| name.starts_with(prefix.as_str()))
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Match a name against a glob pattern, where `*` matches any sequence of characters and `?`
+/// matches any single character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` seen and the name position it was tried at, for backtracking
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("inventory/*", "inventory/Missile"));
+        assert!(glob_match("*/Missile", "inventory/Missile"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("?ombs", "Bombs"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("inventory/*", "inventory"));
+        assert!(!glob_match("a*b", "aXbY"));
+    }
+
+    #[test]
+    fn glob_match_counts_characters_not_bytes() {
+        assert!(glob_match("?", "é"));
+        assert!(glob_match("h?ll?", "hällö"));
+    }
+
+    #[test]
+    fn subscription_matches_prefixes_or_patterns() {
+        let subscription = Subscription::new(vec!["inv".to_owned()], vec!["*Area".to_owned()]);
+        assert!(subscription.matches("inventory/Missile"));
+        assert!(subscription.matches("Current Area"));
+        assert!(!subscription.matches("Play Time"));
+    }
+}
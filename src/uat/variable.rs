@@ -1,26 +1,319 @@
-use std::{borrow::Borrow, collections::HashMap};
+use std::collections::BTreeMap;
 
 use json::JsonValue;
 
+pub const PATH_SEPARATOR: char = '/';
+
+/// Normalize a variable path so that equivalent paths name the same variable, by removing empty
+/// segments, e.g. `/inventory//Missile` becomes `inventory/Missile`.
+pub fn normalize_path(path: &str) -> String {
+    path.split(PATH_SEPARATOR)
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join(&PATH_SEPARATOR.to_string())
+}
+
+pub fn join_path(parent: &str, child: &str) -> String {
+    normalize_path(&format!("{parent}{PATH_SEPARATOR}{child}"))
+}
+
+pub fn is_in_subtree(name: &str, path: &str) -> bool {
+    path.is_empty()
+        || name
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(PATH_SEPARATOR))
+}
+
+/// Split the slot off a variable path whose first segment is the name of one of the interface's
+/// slots, returning the slot's index and the rest of the path.
+pub fn split_slot<'a>(name: &'a str, slots: &[String]) -> (Option<i32>, &'a str) {
+    name.split_once(PATH_SEPARATOR)
+        .filter(|(_, rest)| !rest.is_empty())
+        .and_then(|(first, rest)| {
+            let index = slots.iter().position(|slot| slot == first)?;
+            Some((Some(index as i32), rest))
+        })
+        .unwrap_or((None, name))
+}
+
+/// Whether a value can be written at a path. Only a non-empty object can be written at the root,
+/// since anything else would replace every variable with a single unnamed one.
+pub fn is_writable(name: &str, value: &JsonValue) -> bool {
+    !normalize_path(name).is_empty()
+        || matches!(value, JsonValue::Object(object) if !object.is_empty())
+}
+
+/// Split a value into the variables it stands for. Non-empty objects become one variable per
+/// member, named by appending the member's key to the path. Anything else is a single variable.
+fn flatten(path: String, value: JsonValue, variables: &mut BTreeMap<String, JsonValue>) {
+    match value {
+        JsonValue::Object(object) if !object.is_empty() => {
+            for (key, value) in object.iter() {
+                flatten(join_path(&path, key), value.clone(), variables);
+            }
+        }
+        value => {
+            variables.insert(path, value);
+        }
+    }
+}
+
+/// A change to a variable's value. Variables that were added have no old value, and variables
+/// that were removed have no new value.
+#[derive(Debug, Clone)]
+pub struct VariableChange {
+    pub name: String,
+    pub old_value: Option<JsonValue>,
+    pub new_value: Option<JsonValue>,
+}
+
+impl VariableChange {
+    /// The value to send to clients, where removed variables are sent as null.
+    pub fn value(&self) -> JsonValue {
+        self.new_value.clone().unwrap_or(JsonValue::Null)
+    }
+}
+
+/// The variables sent to clients, arranged in a tree by their `/`-separated paths. Only leaves of
+/// the tree hold values, so writing a value at a path replaces everything beneath it, and writing
+/// beneath a path replaces the value at it.
 #[derive(Debug, Clone)]
-pub struct VariableStore(HashMap<String, JsonValue>);
+pub struct VariableStore(BTreeMap<String, JsonValue>);
 
 impl VariableStore {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self(BTreeMap::new())
+    }
+
+    /// Write a value at a path, returning the variables that changed as a result in order of
+    /// name. Variables that no longer exist are returned with a null value. Values that aren't
+    /// writable at the path are ignored.
+    pub fn update_variable(&mut self, name: &str, value: JsonValue) -> Vec<VariableChange> {
+        if !is_writable(name, &value) {
+            return Vec::new();
+        }
+        let path = normalize_path(name);
+        let mut new_variables = BTreeMap::new();
+        flatten(path.clone(), value, &mut new_variables);
+
+        // Anything above the path can't have a value anymore, and anything in it gets replaced
+        let ancestors = path
+            .match_indices(PATH_SEPARATOR)
+            .map(|(i, _)| &path[..i])
+            .filter(|ancestor| self.0.contains_key(*ancestor))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        let replaced = self
+            .subtree(&path)
+            .map(|(name, _)| name.to_owned())
+            .collect::<Vec<_>>();
+
+        let mut changes = BTreeMap::new();
+        for name in ancestors.into_iter().chain(replaced) {
+            if !new_variables.contains_key(&name) {
+                let old_value = self.0.remove(&name);
+                changes.insert(name, (old_value, None));
+            }
+        }
+        for (name, value) in new_variables {
+            let old_value = self.0.insert(name.clone(), value.clone());
+            if old_value.as_ref() != Some(&value) {
+                changes.insert(name, (old_value, Some(value)));
+            }
+        }
+        changes
+            .into_iter()
+            .map(|(name, (old_value, new_value))| VariableChange {
+                name,
+                old_value,
+                new_value,
+            })
+            .collect()
+    }
+
+    fn remove_variables(&mut self, names: Vec<String>) -> Vec<VariableChange> {
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let old_value = self.0.remove(&name)?;
+                Some(VariableChange {
+                    name,
+                    old_value: Some(old_value),
+                    new_value: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove the variables at or beneath a path, returning them as changes to null. Unlike
+    /// writing null, removed variables are left out of the store entirely.
+    pub fn delete_variable(&mut self, name: &str) -> Vec<VariableChange> {
+        let path = normalize_path(name);
+        let names = self
+            .subtree(&path)
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        self.remove_variables(names)
+    }
+
+    /// Write a value at a path, or remove the variables at and beneath it if there is no value.
+    pub fn apply(&mut self, name: &str, value: Option<JsonValue>) -> Vec<VariableChange> {
+        match value {
+            Some(value) => self.update_variable(name, value),
+            None => self.delete_variable(name),
+        }
+    }
+
+    /// Remove every variable whose name starts with a prefix, returning them as changes to null.
+    pub fn clear_prefix(&mut self, prefix: &str) -> Vec<VariableChange> {
+        let prefix = prefix.trim_start_matches(PATH_SEPARATOR);
+        let names = self
+            .0
+            .range(prefix.to_owned()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .map(|(name, _)| name.clone())
+            .collect();
+        self.remove_variables(names)
     }
 
-    pub fn update_variable(&mut self, name: &str, value: JsonValue) -> bool {
-        let entry = self.0.remove_entry(name);
-        let (key, result) = match entry {
-            Some((key, old_value)) => (key, old_value != value),
-            None => (name.to_owned(), true),
-        };
-        self.0.insert(key, value);
-        result
+    /// Remove every variable, returning them all as changes to null.
+    pub fn clear(&mut self) -> Vec<VariableChange> {
+        self.clear_prefix("")
+    }
+
+    pub fn get(&self, name: &str) -> Option<&JsonValue> {
+        self.0.get(name)
+    }
+
+    /// The value at a path, with the variables beneath it gathered back into nested objects as
+    /// they were written.
+    pub fn value(&self, path: &str) -> Option<JsonValue> {
+        let path = normalize_path(path);
+        if let Some(value) = self.0.get(&path) {
+            return Some(value.clone());
+        }
+        let mut object = JsonValue::new_object();
+        for (name, value) in self.subtree(&path) {
+            let rest = name[path.len()..].trim_start_matches(PATH_SEPARATOR);
+            let mut parent = &mut object;
+            for segment in rest.split(PATH_SEPARATOR) {
+                parent = &mut parent[segment];
+            }
+            *parent = value.clone();
+        }
+        (!object.is_empty()).then_some(object)
     }
 
     pub fn variable_values(&self) -> impl Iterator<Item = (&str, &JsonValue)> {
-        self.0.iter().map(|(key, var)| (key.borrow(), var))
+        self.0.iter().map(|(key, var)| (key.as_str(), var))
+    }
+
+    /// The variables at or beneath a path, in order of name.
+    pub fn subtree<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a str, &'a JsonValue)> {
+        self.0
+            .range(path.to_owned()..)
+            .take_while(move |(name, _)| name.starts_with(path))
+            .filter(move |(name, _)| is_in_subtree(name, path))
+            .map(|(key, var)| (key.as_str(), var))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use json::object;
+
+    use super::*;
+
+    fn names(changes: &[VariableChange]) -> Vec<&str> {
+        changes.iter().map(|change| change.name.as_str()).collect()
+    }
+
+    #[test]
+    fn normalize_path_removes_empty_segments() {
+        assert_eq!(normalize_path("/inventory//Missile/"), "inventory/Missile");
+        assert_eq!(normalize_path("///"), "");
+        assert_eq!(join_path("inventory/", "/Missile"), "inventory/Missile");
+        assert_eq!(join_path("", "Missile"), "Missile");
+    }
+
+    #[test]
+    fn is_in_subtree_matches_whole_segments() {
+        assert!(is_in_subtree("inventory/Missile", "inventory"));
+        assert!(is_in_subtree("inventory", "inventory"));
+        assert!(is_in_subtree("inventory", ""));
+        assert!(!is_in_subtree("inventory2", "inventory"));
+        assert!(!is_in_subtree("inventory", "inventory/Missile"));
+    }
+
+    #[test]
+    fn split_slot_only_splits_known_slots() {
+        let slots = vec!["Link".to_owned(), "Zelda".to_owned()];
+        assert_eq!(split_slot("Zelda/hearts", &slots), (Some(1), "hearts"));
+        assert_eq!(split_slot("Ganon/hearts", &slots), (None, "Ganon/hearts"));
+        assert_eq!(split_slot("Link", &slots), (None, "Link"));
+        assert_eq!(split_slot("Link/", &slots), (None, "Link/"));
+    }
+
+    #[test]
+    fn only_objects_are_writable_at_the_root() {
+        assert!(is_writable("a", &5.into()));
+        assert!(is_writable("/", &object! {a: 1}));
+        assert!(!is_writable("", &5.into()));
+        assert!(!is_writable("/", &JsonValue::Null));
+        assert!(!is_writable("", &object! {}));
+    }
+
+    #[test]
+    fn update_variable_reports_only_changed_variables() {
+        let mut store = VariableStore::new();
+        let changes = store.update_variable("inventory", object! {Missile: 5, Bombs: 1});
+        assert_eq!(names(&changes), ["inventory/Bombs", "inventory/Missile"]);
+        assert!(changes.iter().all(|change| change.old_value.is_none()));
+
+        let changes = store.update_variable("inventory", object! {Missile: 6, Bombs: 1});
+        assert_eq!(names(&changes), ["inventory/Missile"]);
+        assert_eq!(changes[0].old_value, Some(5.into()));
+        assert_eq!(changes[0].new_value, Some(6.into()));
+
+        assert!(store
+            .update_variable("inventory/Missile", 6.into())
+            .is_empty());
+    }
+
+    #[test]
+    fn update_variable_replaces_ancestors_and_descendants() {
+        let mut store = VariableStore::new();
+        store.update_variable("inventory", object! {Missile: 5, Bombs: 1});
+
+        let changes = store.update_variable("inventory", 3.into());
+        assert_eq!(
+            names(&changes),
+            ["inventory", "inventory/Bombs", "inventory/Missile"]
+        );
+        assert_eq!(changes[1].new_value, None);
+
+        let changes = store.update_variable("inventory/Missile", 1.into());
+        assert_eq!(names(&changes), ["inventory", "inventory/Missile"]);
+        assert_eq!(changes[0].value(), JsonValue::Null);
+        assert_eq!(store.value("inventory"), Some(object! {Missile: 1}));
+    }
+
+    #[test]
+    fn update_variable_ignores_scalars_at_the_root() {
+        let mut store = VariableStore::new();
+        store.update_variable("a", 1.into());
+        assert!(store.update_variable("", 5.into()).is_empty());
+        assert_eq!(store.get("a"), Some(&1.into()));
+    }
+
+    #[test]
+    fn delete_and_clear_remove_variables() {
+        let mut store = VariableStore::new();
+        store.update_variable("", object! {a: {b: 1, c: 2}, ab: 3, d: 4});
+
+        assert_eq!(names(&store.delete_variable("/a/")), ["a/b", "a/c"]);
+        assert_eq!(names(&store.clear_prefix("a")), ["ab"]);
+        assert_eq!(names(&store.clear()), ["d"]);
+        assert_eq!(store.variable_values().count(), 0);
     }
 }